@@ -0,0 +1,259 @@
+use log::{debug, trace};
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::file_decoder::FileDecoder;
+
+/// Paces the presentation of frames.
+///
+/// Instead of accumulating the frame durations, which drifts with every rounded duration, the
+/// presentation time of each frame is pulled towards the master clock by a small part (the
+/// loop gain) of the difference. Differences above [`PresentationClock::RESYNC_THRESHOLD`],
+/// e.g. after a seek, resync the clock hard.
+pub struct PresentationClock {
+    gain: f64,
+    // Wall clock time and frame time (in ms) of the last resync, the master clock runs from it.
+    anchor: Option<(Instant, u64)>,
+    presentation_time: Option<Instant>,
+}
+
+impl PresentationClock {
+    /// Corrects a tenth of the drift with every frame.
+    pub const DEFAULT_GAIN: f64 = 0.1;
+    pub const RESYNC_THRESHOLD: Duration = Duration::from_millis(500);
+
+    /// Create a clock with the loop `gain` between 0 (never correct) and 1 (snap to the
+    /// master clock with every frame). Values outside are clamped.
+    pub fn new(gain: f64) -> PresentationClock {
+        PresentationClock {
+            gain: gain.clamp(0.0, 1.0),
+            anchor: None,
+            presentation_time: None,
+        }
+    }
+
+    /// Forget the timing, the next frame is presented immediately and restarts the master
+    /// clock. Call it after seeks and when resuming from pause.
+    pub fn reset(&mut self) {
+        self.anchor = None;
+        self.presentation_time = None;
+    }
+
+    /// Returns when the frame with `frame_time` (in ms), shown `diff_to_prev_frame` ms after
+    /// the previous frame, should be presented. The stream time runs `speed` times faster
+    /// than the wall clock, change the speed only together with a [`PresentationClock::reset`].
+    pub fn schedule(
+        &mut self,
+        frame_time: u64,
+        diff_to_prev_frame: u64,
+        speed: f64,
+        now: Instant,
+    ) -> Instant {
+        let (presentation_time, (anchor_time, anchor_frame_time)) =
+            match (self.presentation_time, self.anchor) {
+                (Some(presentation_time), Some(anchor)) => (presentation_time, anchor),
+                _ => return self.resync(frame_time, now),
+            };
+        if frame_time < anchor_frame_time {
+            debug!("frame time {} before clock start, resync", frame_time);
+            return self.resync(frame_time, now);
+        }
+
+        let predicted =
+            presentation_time + Duration::from_secs_f64(diff_to_prev_frame as f64 / speed / 1000.0);
+        let master = anchor_time
+            + Duration::from_secs_f64((frame_time - anchor_frame_time) as f64 / speed / 1000.0);
+        let drift = if master > predicted {
+            (master - predicted).as_secs_f64()
+        } else {
+            -(predicted - master).as_secs_f64()
+        };
+        if drift.abs() > PresentationClock::RESYNC_THRESHOLD.as_secs_f64() {
+            debug!("clock drift {:.3}s too big, resync", drift);
+            return self.resync(frame_time, now);
+        }
+
+        let correction = drift * self.gain;
+        trace!("clock drift {:.4}s, correct by {:.4}s", drift, correction);
+        let presentation_time = if correction >= 0.0 {
+            predicted + Duration::from_secs_f64(correction)
+        } else {
+            predicted - Duration::from_secs_f64(-correction)
+        };
+        self.presentation_time = Some(presentation_time);
+        presentation_time
+    }
+
+    fn resync(&mut self, frame_time: u64, now: Instant) -> Instant {
+        self.anchor = Some((now, frame_time));
+        self.presentation_time = Some(now);
+        now
+    }
+}
+
+/// Playback position of the audio, the master clock for the video while audio plays.
+///
+/// The audio consumer reports the audio it hands to the device and how much of it the device
+/// still buffers, the position is the end of the queued audio minus the buffered part.
+#[derive(Default)]
+pub struct AudioClock {
+    started: AtomicBool,
+    // Frame time (in ms) of the end of the audio queued to the device.
+    queued_end_ms: AtomicU64,
+    // Samples per channel queued to the device, but not played yet.
+    buffered_samples: AtomicU64,
+}
+
+impl AudioClock {
+    /// Forget the position, e.g. after a seek. Until audio is queued again there is no
+    /// position.
+    pub fn reset(&self) {
+        self.started.store(false, Ordering::Release);
+        self.buffered_samples.store(0, Ordering::Relaxed);
+    }
+
+    /// Report that `samples` (per channel) starting at `frame_time` (in ms) were queued to
+    /// the device. At a playback speed other than 1 count the samples before the speed change,
+    /// i.e. in stream time.
+    pub fn queued(&self, frame_time: u64, samples: u64) {
+        let duration_ms = samples * 1000 / FileDecoder::AUDIO_SAMPLE_RATE as u64;
+        self.queued_end_ms
+            .store(frame_time + duration_ms, Ordering::Relaxed);
+        self.buffered_samples.fetch_add(samples, Ordering::Relaxed);
+        self.started.store(true, Ordering::Release);
+    }
+
+    /// Report how many samples (per channel) the device still buffers, in stream time like
+    /// [`AudioClock::queued`].
+    pub fn set_buffered(&self, samples: u64) {
+        self.buffered_samples.store(samples, Ordering::Relaxed);
+    }
+
+    /// Frame time (in ms) of the audio playing right now. `None` before audio was queued and
+    /// while the device runs empty, e.g. after the end of the audio.
+    pub fn time_ms(&self) -> Option<u64> {
+        let buffered_samples = self.buffered_samples.load(Ordering::Relaxed);
+        if !self.started.load(Ordering::Acquire) || buffered_samples == 0 {
+            return None;
+        }
+        let buffered_ms = buffered_samples * 1000 / FileDecoder::AUDIO_SAMPLE_RATE as u64;
+        Some(
+            self.queued_end_ms
+                .load(Ordering::Relaxed)
+                .saturating_sub(buffered_ms),
+        )
+    }
+}
+
+/// Clock the playback is synchronized to, see [`crate::FileDecoderBuilder::sync_mode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    /// The video follows the [`AudioClock`], the default with audio.
+    AudioMaster,
+    /// The video is presented at its own pace without dropping frames, the audio lagging
+    /// behind it is dropped, see [`audio_is_late`].
+    VideoMaster,
+    /// The video follows the system clock, the default without audio. The audio lagging
+    /// behind the video is dropped like with [`SyncMode::VideoMaster`].
+    ExternalClock,
+}
+
+// Late frames or audio within this limit are still presented, so a slow frame does not cause
+// a drop right away.
+const LATE_LIMIT: Duration = Duration::from_millis(100);
+
+/// What to do with a frame scheduled against a master clock, see [`schedule_against`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameAction {
+    /// Present the frame at the given time, until then the previous frame stays on screen.
+    Present(Instant),
+    /// The frame is too late, skip it.
+    Drop,
+}
+
+/// Schedule the frame with `frame_time` (in ms) against the `master_ms` clock, e.g. the
+/// [`AudioClock`], with the stream time running `speed` times faster than the wall clock.
+pub fn schedule_against(frame_time: u64, master_ms: u64, speed: f64, now: Instant) -> FrameAction {
+    if frame_time >= master_ms {
+        let ahead = Duration::from_secs_f64((frame_time - master_ms) as f64 / speed / 1000.0);
+        // The video is far ahead after discontinuities, do not wait for the audio forever.
+        return FrameAction::Present(now + ahead.min(PresentationClock::RESYNC_THRESHOLD));
+    }
+    let late = Duration::from_secs_f64((master_ms - frame_time) as f64 / speed / 1000.0);
+    if late > LATE_LIMIT {
+        trace!("frame {} late by {:?}, drop it", frame_time, late);
+        FrameAction::Drop
+    } else {
+        FrameAction::Present(now)
+    }
+}
+
+/// True if the audio at `frame_time` (in ms) lags so far behind the `master_ms` clock, e.g.
+/// the position of the video, that it is dropped instead of played.
+pub fn audio_is_late(frame_time: u64, master_ms: u64) -> bool {
+    frame_time + (LATE_LIMIT.as_millis() as u64) < master_ms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Instant, expected: Instant) {
+        let diff = if actual > expected {
+            actual - expected
+        } else {
+            expected - actual
+        };
+        assert!(diff < Duration::from_micros(1), "off by {:?}", diff);
+    }
+
+    #[test]
+    fn small_drift_is_smoothed() {
+        let start = Instant::now();
+        let mut clock = PresentationClock::new(PresentationClock::DEFAULT_GAIN);
+        assert_eq!(clock.schedule(0, 0, 1.0, start), start);
+        // A frame duration rounded down by 10ms is only corrected by a tenth.
+        let presentation_time = clock.schedule(40, 30, 1.0, start);
+        assert_close(presentation_time, start + Duration::from_millis(31));
+    }
+
+    #[test]
+    fn full_gain_snaps_to_master() {
+        let start = Instant::now();
+        let mut clock = PresentationClock::new(2.0);
+        clock.schedule(0, 0, 1.0, start);
+        let presentation_time = clock.schedule(40, 30, 1.0, start);
+        assert_close(presentation_time, start + Duration::from_millis(40));
+    }
+
+    #[test]
+    fn large_jump_resyncs() {
+        let start = Instant::now();
+        let mut clock = PresentationClock::new(PresentationClock::DEFAULT_GAIN);
+        clock.schedule(0, 0, 1.0, start);
+        let now = start + Duration::from_millis(40);
+        assert_eq!(clock.schedule(2000, 40, 1.0, now), now);
+        // The clock runs from the new position.
+        let presentation_time = clock.schedule(2040, 40, 1.0, now);
+        assert_close(presentation_time, now + Duration::from_millis(40));
+    }
+
+    #[test]
+    fn backward_seek_resyncs() {
+        let start = Instant::now();
+        let mut clock = PresentationClock::new(PresentationClock::DEFAULT_GAIN);
+        clock.schedule(1000, 0, 1.0, start);
+        let now = start + Duration::from_millis(40);
+        assert_eq!(clock.schedule(960, 40, 1.0, now), now);
+    }
+
+    #[test]
+    fn late_audio() {
+        assert!(!audio_is_late(1000, 1000));
+        assert!(!audio_is_late(1000, 1100));
+        assert!(audio_is_late(1000, 1101));
+        assert!(!audio_is_late(2000, 1000));
+    }
+}
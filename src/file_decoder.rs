@@ -2,23 +2,43 @@ extern crate ffmpeg_rs;
 use blocking_delay_queue::{BlockingDelayQueue, DelayItem};
 pub use error_stack::{Context, IntoReport, Report, Result, ResultExt};
 use ffmpeg_rs::{
-    format::{input, Pixel},
+    color,
+    format::{self, input, stream::Disposition, Pixel},
     mathematics::Rounding,
     media::Type,
+    picture,
     rescale::TIME_BASE,
-    software::scaling::{context, flag::Flags},
-    util::frame::video::Video,
-    Packet, {Rational, Rescale},
+    software::{
+        resampling,
+        scaling::{context, flag::Flags},
+    },
+    subtitle,
+    util::frame::{audio::Audio, video::Video},
+    ChannelLayout, Dictionary, Packet, Subtitle, {Rational, Rescale},
 };
-use log::{debug, error, trace, warn};
+use log::{debug, error, info, trace, warn};
 use std::fmt;
 use std::{
+    collections::VecDeque,
+    ffi::CString,
+    fs,
     mem::swap,
     ops::RangeFull,
-    path::Path,
-    sync::{mpsc, mpsc::channel, Arc, Weak},
+    slice,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        mpsc,
+        mpsc::channel,
+        Arc, Mutex, Once,
+    },
     thread::{self, JoinHandle},
-    time::Instant,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::{AudioClock, SyncMode},
+    timestamp_overlay,
+    video_filter::VideoFilter,
 };
 
 #[derive(Debug)]
@@ -32,8 +52,108 @@ impl fmt::Display for FileDecoderError {
 
 impl Context for FileDecoderError {}
 
+/// Attached to the report of [`FileDecoder::init`] if a network input cannot be opened, e.g.
+/// because the host is unreachable or the open timeout expired. Check for it with
+/// `report.contains::<ConnectionError>()`.
+#[derive(Debug)]
+pub struct ConnectionError;
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Connection error")
+    }
+}
+
+impl Context for ConnectionError {}
+
+/// Attached to the report of [`FileDecoder::init`] if the input has no video stream, e.g. an
+/// audio file. Check for it like for [`ConnectionError`].
+#[derive(Debug)]
+pub struct NoVideoStream;
+
+impl fmt::Display for NoVideoStream {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("No video stream")
+    }
+}
+
+impl Context for NoVideoStream {}
+
+/// Snapshot of the decoder statistics, see [`FileDecoder::stats`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    /// Bitrate of the video stream as declared by the stream or container in kbit/s.
+    pub declared_bitrate_kbps: Option<u64>,
+    /// Bitrate of the video stream measured by the demuxer over the last second of
+    /// packets in kbit/s. Starts again from 0 after a seek.
+    pub measured_bitrate_kbps: u64,
+    /// Time `init()` took to open and probe the input.
+    pub open_latency: Option<Duration>,
+    /// Time from the last completed seek request until the decoder delivered the first
+    /// frame with its serial.
+    pub seek_latency: Option<Duration>,
+    /// Frames the consumer kept on screen because the video queue ran empty, see
+    /// [`FileDecoder::frame_repeated`].
+    pub repeated_frames: u64,
+    /// Frames the consumer skipped because they were too late, see
+    /// [`FileDecoder::frame_dropped`].
+    pub dropped_frames: u64,
+    /// Frames of the video stream the decoder delivered to the video queue.
+    pub decoded_frames: u64,
+    /// Frames the consumer presented, see [`FileDecoder::frame_presented`].
+    pub displayed_frames: u64,
+    /// Frames waiting in the video queue.
+    pub queue_depth: usize,
+}
+
+#[derive(Default)]
+struct StatsCounters {
+    measured_bitrate_kbps: AtomicU64,
+    decoded_frames: AtomicU64,
+    // Serial and request time of the seek still waiting for its first frame.
+    seek_requested: Mutex<Option<(u64, Instant)>>,
+    seek_latency: Mutex<Option<Duration>>,
+}
+
+/// Flags for the seek in the demuxer, the default seeks to the keyframe closest to the
+/// target.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SeekFlags {
+    /// Only seek to a keyframe at or before the target.
+    pub backward: bool,
+    /// Allow seeking to non-keyframes, decoding may show artifacts until the next keyframe.
+    pub any: bool,
+    /// Seek to the byte offset estimated from the bitrate of the file instead of a timestamp.
+    /// Helps for files with a broken or missing index. Without a known bitrate the seek uses
+    /// the timestamp.
+    pub byte: bool,
+}
+
+/// Events about the decoder state, see [`FileDecoder::try_recv_event`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecoderEvent {
+    /// A seek to `target` was requested, frames of the seek carry `serial`.
+    SeekRequested { serial: u64, target: Duration },
+    /// The decoder produced the first frame after the seek, usually the keyframe the
+    /// demuxer landed on.
+    SeekKeyframeReached { serial: u64, position: Duration },
+    /// The first frame of the seek was presented, see [`FileDecoder::frame_presented`].
+    SeekCompleted {
+        serial: u64,
+        target: Duration,
+        position: Duration,
+    },
+    /// The decoder queued the EOF item of the frames with `serial`, at the end of the input
+    /// or the trim. Not sent while looping, the playback restarts then.
+    EndOfStream { serial: u64 },
+}
+
 type PacketQueue = Arc<BlockingDelayQueue<DelayItem<Option<PacketData>>>>;
 pub type VideoQueue = Arc<BlockingDelayQueue<DelayItem<Option<VideoData>>>>;
+pub type AudioQueue = Arc<BlockingDelayQueue<DelayItem<Option<AudioData>>>>;
+pub type SubtitleQueue = Arc<BlockingDelayQueue<DelayItem<Option<SubtitleData>>>>;
+/// Callback for the frames of the main video stream, see [`FileDecoder::on_frame`].
+pub type FrameCallback = Box<dyn FnMut(&VideoData) + Send>;
 
 #[derive(new)]
 #[allow(clippy::too_many_arguments)]
@@ -41,25 +161,402 @@ pub struct FileDecoderBuilder {
     uri: String,
     #[new(value = "Pixel::YUV420P")]
     pixel_format: Pixel,
+    #[new(default)]
+    trim_start: Option<Duration>,
+    #[new(default)]
+    trim_end: Option<Duration>,
+    #[new(default)]
+    concat_files: Vec<String>,
+    #[new(default)]
+    output_fps: Option<f64>,
+    #[new(default)]
+    duration_override: Option<Duration>,
+    #[new(default)]
+    ignore_duration: bool,
+    #[new(default)]
+    estimate_duration: bool,
+    #[new(default)]
+    seek_flags: SeekFlags,
+    #[new(default)]
+    extra_video_streams: Vec<usize>,
+    #[new(default)]
+    input_color_range: Option<color::Range>,
+    #[new(default)]
+    output_color_range: Option<color::Range>,
+    #[new(default)]
+    lowres: u8,
+    #[new(default)]
+    burn_in_timestamps: bool,
+    #[new(default)]
+    frame_cache_limit: Option<FrameCacheLimit>,
+    #[new(default)]
+    disable_audio: bool,
+    #[new(value = "FileDecoder::PACKET_QUEUE_SIZE")]
+    packet_queue_capacity: usize,
+    #[new(value = "FileDecoder::FRAME_QUEUE_SIZE")]
+    frame_queue_capacity: usize,
+    #[new(default)]
+    hwaccel: Option<HwAccel>,
+    #[new(default)]
+    video_stream_index: Option<usize>,
+    #[new(default)]
+    auto_pixel_formats: Vec<Pixel>,
+    #[new(default)]
+    open_timeout: Option<Duration>,
+    #[new(default)]
+    subtitles: bool,
+    #[new(default)]
+    subtitle_stream_index: Option<usize>,
+    #[new(default)]
+    output_size: Option<(u32, u32)>,
+    #[new(default)]
+    deinterlace: bool,
+    #[new(default)]
+    accurate_seek: bool,
+    #[new(value = "Flags::BILINEAR")]
+    scaler_flags: Flags,
+    #[new(default)]
+    keyframes_only: bool,
+    #[new(default)]
+    burn_in_subtitles: bool,
+    #[new(value = "VolumeCurve::Logarithmic")]
+    volume_curve: VolumeCurve,
+    #[new(default)]
+    keep_hw_frames: bool,
+    #[new(default)]
+    sync_mode: Option<SyncMode>,
 }
 
 impl FileDecoderBuilder {
     pub fn build(&self) -> Result<FileDecoder, FileDecoderError> {
-        let mut file_decoder = FileDecoder::new(self.uri.to_owned(), self.pixel_format);
+        let mut file_decoder = self.build_uninitialized();
         file_decoder.init()?;
         Ok(file_decoder)
     }
 
+    // The decoder with the settings, but without opening the input.
+    fn build_uninitialized(&self) -> FileDecoder {
+        FileDecoder::new(
+            self.uri.to_owned(),
+            self.pixel_format,
+            self.trim_start,
+            self.trim_end,
+            self.concat_files.clone(),
+            self.output_fps,
+            self.duration_override,
+            self.ignore_duration,
+            self.estimate_duration,
+            self.seek_flags,
+            self.extra_video_streams.clone(),
+            self.input_color_range,
+            self.output_color_range,
+            self.lowres,
+            self.burn_in_timestamps,
+            self.frame_cache_limit,
+            self.disable_audio,
+            self.packet_queue_capacity,
+            self.frame_queue_capacity,
+            self.hwaccel,
+            self.video_stream_index,
+            self.auto_pixel_formats.clone(),
+            self.open_timeout,
+            self.subtitles,
+            self.subtitle_stream_index,
+            self.output_size,
+            self.deinterlace,
+            self.accurate_seek,
+            self.scaler_flags,
+            self.keyframes_only,
+            self.burn_in_subtitles,
+            self.volume_curve,
+            self.keep_hw_frames,
+            self.sync_mode,
+        )
+    }
+
     pub fn pixel_format(&mut self, pix_fmt: Pixel) -> &mut FileDecoderBuilder {
         self.pixel_format = pix_fmt;
         self
     }
 
-    #[allow(dead_code)]
+    /// Keep the pixel format of the decoder if it is one of `displayable` (e.g. the formats the
+    /// consumer can render directly), which skips the conversion. Other formats are converted
+    /// to the pixel format set with [`FileDecoderBuilder::pixel_format`]. Check the chosen
+    /// format with [`FileDecoder::pixel_format`]. Empty (always convert) by default.
+    pub fn auto_pixel_format(&mut self, displayable: Vec<Pixel>) -> &mut FileDecoderBuilder {
+        self.auto_pixel_formats = displayable;
+        self
+    }
+
+    /// Give up opening a network input (e.g. http or rtsp) and reading from it after
+    /// `timeout` without data, instead of waiting forever for a dead source. The failure
+    /// carries a [`ConnectionError`]. No timeout by default.
+    pub fn open_timeout(&mut self, timeout: Duration) -> &mut FileDecoderBuilder {
+        self.open_timeout = Some(timeout);
+        self
+    }
+
     pub fn uri(&mut self, uri: String) -> &mut FileDecoderBuilder {
         self.uri = uri;
         self
     }
+
+    /// Play the given files one after another as a single input with a continuous timeline
+    /// using ffmpeg's concat demuxer. The uri is ignored in this case. A concat script
+    /// ("ffconcat version 1.0") can also be opened directly as uri.
+    pub fn concat(&mut self, files: Vec<String>) -> &mut FileDecoderBuilder {
+        self.concat_files = files;
+        self
+    }
+
+    /// Deliver frames at a fixed rate instead of the source frame rate by dropping or
+    /// repeating frames based on their timestamps. Meant for export, leave it off (`None`)
+    /// for normal playback.
+    pub fn output_fps(&mut self, fps: Option<f64>) -> &mut FileDecoderBuilder {
+        self.output_fps = fps.filter(|fps| {
+            let valid = fps.is_finite() && *fps > 0.0;
+            if !valid {
+                warn!("ignore invalid output fps {}", fps);
+            }
+            valid
+        });
+        self
+    }
+
+    /// Use `duration` instead of the duration declared by the container.
+    pub fn duration_override(&mut self, duration: Option<Duration>) -> &mut FileDecoderBuilder {
+        self.duration_override = duration;
+        self
+    }
+
+    /// Ignore the duration declared by the container and treat it as unknown.
+    pub fn ignore_duration(&mut self, ignore: bool) -> &mut FileDecoderBuilder {
+        self.ignore_duration = ignore;
+        self
+    }
+
+    /// Estimate the duration from the timestamp of the last packet instead of trusting the
+    /// container. This reads the whole file during `init()`, so it is slow for big files.
+    pub fn estimate_duration(&mut self, estimate: bool) -> &mut FileDecoderBuilder {
+        self.estimate_duration = estimate;
+        self
+    }
+
+    pub fn seek_flags(&mut self, seek_flags: SeekFlags) -> &mut FileDecoderBuilder {
+        self.seek_flags = seek_flags;
+        self
+    }
+
+    /// Algorithm of the scaler which converts and resizes the frames, `Flags::BILINEAR` by
+    /// default. `Flags::POINT` (nearest neighbor) is the fastest and keeps hard edges, e.g.
+    /// for pixel art. `Flags::BICUBIC` is slower, but sharper when downscaling.
+    pub fn scaler_flags(&mut self, flags: Flags) -> &mut FileDecoderBuilder {
+        self.scaler_flags = flags;
+        self
+    }
+
+    /// Decode only the keyframes, the decoder skips all other frames. Fast enough to e.g.
+    /// extract thumbnails together with [`FileDecoder::seek_fraction`]. The frames go to the
+    /// video queue as usual, their frame times are as far apart as the keyframes. Not every
+    /// codec supports skipping frames, rawvideo and intra-only codecs have only keyframes
+    /// anyway. Off by default.
+    pub fn keyframes_only(&mut self, enable: bool) -> &mut FileDecoderBuilder {
+        self.keyframes_only = enable;
+        self
+    }
+
+    /// Seeks land on the requested position instead of the keyframe before it: the decoders
+    /// drop the frames between the keyframe and the target. Seeking takes longer the further
+    /// the keyframes are apart. Seeks to keyframes are not affected. Off by default.
+    pub fn accurate_seek(&mut self, enable: bool) -> &mut FileDecoderBuilder {
+        self.accurate_seek = enable;
+        self
+    }
+
+    /// Also decode the video streams with the given stream indices next to the main video
+    /// stream, e.g. for A/B comparisons. Each stream gets its own decoder and queue, see
+    /// [`FileDecoder::extra_video_queue`]. All queues share the demuxer, so the consumer has
+    /// to pull frames from every queue or the others stall. Empty by default.
+    pub fn extra_video_streams(&mut self, stream_indices: Vec<usize>) -> &mut FileDecoderBuilder {
+        self.extra_video_streams = stream_indices;
+        self
+    }
+
+    /// Force the color range the scaler assumes for the decoded frames (`input`) and converts
+    /// to (`output`) instead of the one derived from the pixel formats. Meant for files with
+    /// wrong range metadata, a wrong override washes out or crushes the image. `None` keeps
+    /// the default for the respective side.
+    pub fn color_range_override(
+        &mut self,
+        input: Option<color::Range>,
+        output: Option<color::Range>,
+    ) -> &mut FileDecoderBuilder {
+        self.input_color_range = input;
+        self.output_color_range = output;
+        self
+    }
+
+    /// Let the decoder output frames at a reduced resolution, 1 for half, 2 for a quarter and
+    /// 3 for an eighth of the size. Much faster than scaling down after decoding, but only
+    /// supported by some codecs. Higher levels than the codec supports are reduced, 0 (the
+    /// default) decodes at full resolution.
+    pub fn lowres(&mut self, level: u8) -> &mut FileDecoderBuilder {
+        self.lowres = level;
+        self
+    }
+
+    /// Draw the frame time and pts into the top left corner of the frame pixels, so they show
+    /// up in screenshots and exports. For debugging pipelines, off by default. Only supported
+    /// for 8 bit YUV output pixel formats.
+    pub fn burn_in_timestamps(&mut self, burn_in: bool) -> &mut FileDecoderBuilder {
+        self.burn_in_timestamps = burn_in;
+        self
+    }
+
+    /// Keep the most recently decoded frames up to `limit` in memory, so stepping back with
+    /// [`FileDecoder::step_back`] does not need to seek and decode again. Off (`None`) by
+    /// default.
+    pub fn frame_cache(&mut self, limit: Option<FrameCacheLimit>) -> &mut FileDecoderBuilder {
+        self.frame_cache_limit = limit;
+        self
+    }
+
+    /// Decode the best subtitle stream into the [`SubtitleQueue`]. Only text subtitles (e.g.
+    /// SRT and ASS) are supported, bitmap subtitles are skipped. Off by default.
+    pub fn subtitles(&mut self, enable: bool) -> &mut FileDecoderBuilder {
+        self.subtitles = enable;
+        self
+    }
+
+    /// Draw the subtitles into the frames of the main video stream instead of decoding them
+    /// into the [`SubtitleQueue`], so exported frames and thumbnails contain them. Uses the
+    /// stream of [`FileDecoderBuilder::subtitle_stream_index`] or the best subtitle stream.
+    /// Needs FFmpeg's subtitles filter (libass) and supports text subtitles only. Off by
+    /// default.
+    pub fn burn_in_subtitles(&mut self, burn_in: bool) -> &mut FileDecoderBuilder {
+        self.burn_in_subtitles = burn_in;
+        self
+    }
+
+    /// How [`FileDecoder::set_volume`] maps the volume to the gain of the audio,
+    /// [`VolumeCurve::Logarithmic`] by default.
+    pub fn volume_curve(&mut self, curve: VolumeCurve) -> &mut FileDecoderBuilder {
+        self.volume_curve = curve;
+        self
+    }
+
+    /// Clock to synchronize the playback to, see [`FileDecoder::sync_mode`]. By default the
+    /// audio if there is audio, the system clock otherwise.
+    pub fn sync_mode(&mut self, sync_mode: SyncMode) -> &mut FileDecoderBuilder {
+        self.sync_mode = Some(sync_mode);
+        self
+    }
+
+    /// Decode the subtitle stream with `index` instead of the best one, see
+    /// [`FileDecoder::list_streams`]. Enables the subtitles.
+    pub fn subtitle_stream_index(&mut self, index: usize) -> &mut FileDecoderBuilder {
+        self.subtitles = true;
+        self.subtitle_stream_index = Some(index);
+        self
+    }
+
+    /// Scale the frames of the main video stream to `size` (width, height) instead of
+    /// delivering them in their native size, e.g. to downscale big videos to the screen.
+    /// `None` (the default) keeps the native size, sizes with a 0 are ignored.
+    pub fn output_size(&mut self, size: Option<(u32, u32)>) -> &mut FileDecoderBuilder {
+        match size {
+            Some((width, height)) if width == 0 || height == 0 => {
+                warn!("ignore output size {}x{}", width, height)
+            }
+            size => self.output_size = size,
+        }
+        self
+    }
+
+    /// Deinterlace the frames of the main video stream flagged as interlaced with FFmpeg's
+    /// yadif filter. Every field becomes a frame, so interlaced content plays at twice its
+    /// frame rate. Off by default.
+    pub fn deinterlace(&mut self, enable: bool) -> &mut FileDecoderBuilder {
+        self.deinterlace = enable;
+        self
+    }
+
+    /// Do not decode the audio, the video plays silently.
+    pub fn disable_audio(&mut self, disable: bool) -> &mut FileDecoderBuilder {
+        self.disable_audio = disable;
+        self
+    }
+
+    /// Number of packets buffered per stream between the demuxer and the decoders, 60 by
+    /// default. Deeper buffering helps with high bitrates on slow storage. 0 is ignored.
+    pub fn packet_queue_capacity(&mut self, capacity: usize) -> &mut FileDecoderBuilder {
+        if capacity == 0 {
+            warn!("ignore packet queue capacity 0");
+        } else {
+            self.packet_queue_capacity = capacity;
+        }
+        self
+    }
+
+    /// Number of decoded frames buffered per video stream for the consumer, 3 by default.
+    /// Fewer frames lower the latency, more frames absorb decoding hiccups. 0 is ignored.
+    pub fn frame_queue_capacity(&mut self, capacity: usize) -> &mut FileDecoderBuilder {
+        if capacity == 0 {
+            warn!("ignore frame queue capacity 0");
+        } else {
+            self.frame_queue_capacity = capacity;
+        }
+        self
+    }
+
+    /// Decode the video with the hardware decoder of `hwaccel`. The frames are copied to system
+    /// memory before scaling, so the output pixel formats stay the same. Falls back to software
+    /// decoding if the codec or the system does not support it.
+    pub fn hwaccel(&mut self, hwaccel: HwAccel) -> &mut FileDecoderBuilder {
+        self.hwaccel = Some(hwaccel);
+        self
+    }
+
+    /// Keep the frames of the [`FileDecoderBuilder::hwaccel`] decoder in GPU memory and hand
+    /// them out as [`VideoData::hw_frame`] with an empty [`VideoData::video_frame`], for
+    /// sinks rendering on the GPU. The output size and pixel format, filters and timestamp
+    /// burn-in do not apply to them. Frames decoded in software, e.g. after the fallback, are
+    /// processed as usual. Queued frames hold surfaces of the decoder, so keep the frame
+    /// queue small. Off by default, the SDL player downloads the frames.
+    pub fn keep_hw_frames(&mut self, keep: bool) -> &mut FileDecoderBuilder {
+        self.keep_hw_frames = keep;
+        self
+    }
+
+    /// Play the video stream with the given stream index instead of the one ffmpeg picks as
+    /// best, e.g. for files with multiple camera angles. See [`FileDecoder::list_streams`].
+    /// `init()` fails if the stream is no video stream.
+    pub fn video_stream_index(&mut self, stream_index: Option<usize>) -> &mut FileDecoderBuilder {
+        self.video_stream_index = stream_index;
+        self
+    }
+
+    /// Start decoding at `start` instead of the beginning, like [`FileDecoderBuilder::trim`]
+    /// without an end.
+    pub fn start_at(&mut self, start: Duration) -> &mut FileDecoderBuilder {
+        self.trim_start = Some(start);
+        self
+    }
+
+    /// Limit decoding to the range from `start` to `end` (inclusive). The decoder seeks to
+    /// `start` when started, drops the frames before it and sends EOF once a frame lies
+    /// behind `end`. An inverted range is swapped.
+    pub fn trim(&mut self, start: Duration, end: Duration) -> &mut FileDecoderBuilder {
+        let (start, end) = if start <= end {
+            (start, end)
+        } else {
+            warn!("trim start {:?} behind end {:?}, swap them", start, end);
+            (end, start)
+        };
+        self.trim_start = Some(start);
+        self.trim_end = Some(end);
+        self
+    }
 }
 
 #[derive(new)]
@@ -67,63 +564,502 @@ impl FileDecoderBuilder {
 pub struct FileDecoder {
     uri: String,
     pixel_format: Pixel,
+    trim_start: Option<Duration>,
+    trim_end: Option<Duration>,
+    concat_files: Vec<String>,
+    output_fps: Option<f64>,
+    duration_override: Option<Duration>,
+    ignore_duration: bool,
+    estimate_duration: bool,
+    seek_flags: SeekFlags,
+    extra_video_streams: Vec<usize>,
+    input_color_range: Option<color::Range>,
+    output_color_range: Option<color::Range>,
+    lowres: u8,
+    burn_in_timestamps: bool,
+    frame_cache_limit: Option<FrameCacheLimit>,
+    disable_audio: bool,
+    packet_queue_capacity: usize,
+    frame_queue_capacity: usize,
+    hwaccel: Option<HwAccel>,
+    video_stream_index: Option<usize>,
+    auto_pixel_formats: Vec<Pixel>,
+    open_timeout: Option<Duration>,
+    subtitles: bool,
+    subtitle_stream_index: Option<usize>,
+    output_size: Option<(u32, u32)>,
+    deinterlace: bool,
+    accurate_seek: bool,
+    scaler_flags: Flags,
+    keyframes_only: bool,
+    burn_in_subtitles: bool,
+    volume_curve: VolumeCurve,
+    keep_hw_frames: bool,
+    sync_mode: Option<SyncMode>,
+    // The pixel format of the frames, the decoder's own one with auto_pixel_formats.
+    #[new(value = "pixel_format")]
+    output_pixel_format: Pixel,
     #[new(default)]
     width: u32,
     #[new(default)]
     height: u32,
-    #[new(
-        value = "Arc::new(BlockingDelayQueue::new_with_capacity(FileDecoder::PACKET_QUEUE_SIZE))"
-    )]
+    #[new(value = "Arc::new(BlockingDelayQueue::new_with_capacity(packet_queue_capacity))")]
     packet_queue: PacketQueue,
-    #[new(
-        value = "Arc::new(BlockingDelayQueue::new_with_capacity(FileDecoder::FRAME_QUEUE_SIZE))"
-    )]
+    #[new(value = "Arc::new(BlockingDelayQueue::new_with_capacity(frame_queue_capacity))")]
     video_queue: VideoQueue,
     #[new(default)]
-    running: Option<Arc<bool>>,
+    extra_streams: Vec<ExtraVideoStream>,
+    #[new(default)]
+    running: Option<Arc<AtomicBool>>,
     #[new(default)]
     seek_serial: u64,
+    // Copy of seek_serial for the delivery thread.
+    #[new(default)]
+    current_serial: Arc<AtomicU64>,
+    #[new(default)]
+    frame_callback: Arc<Mutex<Option<FrameCallback>>>,
     #[new(default)]
     threads: Vec<JoinHandle<Result<(), FileDecoderError>>>,
     // Sender for demuxer:
     #[new(default)]
-    demuxer_seek_sender: Option<mpsc::Sender<i64>>,
+    demuxer_seek_sender: Option<mpsc::Sender<DemuxerSeek>>,
     #[new(default)]
     demuxer_serial_sender: Option<mpsc::Sender<u64>>,
-    // Sender for decoder:
+    // Sender for decoders, the main decoder comes first:
     #[new(default)]
-    decoder_serial_sender: Option<mpsc::Sender<u64>>,
+    decoder_serial_senders: Vec<mpsc::Sender<DecoderSerial>>,
+    #[new(value = "Arc::new(BlockingDelayQueue::new_with_capacity(packet_queue_capacity))")]
+    audio_packet_queue: PacketQueue,
+    #[new(
+        value = "Arc::new(BlockingDelayQueue::new_with_capacity(FileDecoder::AUDIO_QUEUE_SIZE))"
+    )]
+    audio_queue: AudioQueue,
+    #[new(default)]
+    has_audio: bool,
+    #[new(value = "Arc::new(AtomicU32::new(FileDecoder::MAX_VOLUME))")]
+    volume: Arc<AtomicU32>,
+    #[new(default)]
+    muted: Arc<AtomicBool>,
+    #[new(value = "Arc::new(BlockingDelayQueue::new_with_capacity(packet_queue_capacity))")]
+    subtitle_packet_queue: PacketQueue,
+    #[new(
+        value = "Arc::new(BlockingDelayQueue::new_with_capacity(FileDecoder::SUBTITLE_QUEUE_SIZE))"
+    )]
+    subtitle_queue: SubtitleQueue,
+    #[new(default)]
+    has_subtitles: bool,
+    // Filter burning the subtitles into the frames, see FileDecoderBuilder::burn_in_subtitles.
+    #[new(default)]
+    subtitle_filter: Option<String>,
+    #[new(default)]
+    eq: Arc<SharedEq>,
     #[new(value = "None")]
-    demuxer_data: Option<DemuxerData>,
+    subtitle_decoder_data: Option<SubtitleDecoderData>,
+    #[new(default)]
+    audio_clock: Arc<AudioClock>,
+    #[new(value = "None")]
+    audio_decoder_data: Option<AudioDecoderData>,
     #[new(value = "None")]
-    decoder_data: Option<DecoderData>,
+    demuxer_data: Option<DemuxerData>,
+    #[new(default)]
+    decoder_data: Vec<DecoderData>,
+    #[new(default)]
+    cover_art: Option<Vec<u8>>,
+    // Start and title of the chapters, sorted by start.
+    #[new(default)]
+    chapters: Vec<(Duration, String)>,
+    #[new(default)]
+    declared_bitrate_kbps: Option<u64>,
+    #[new(default)]
+    media_info: Option<MediaInfo>,
+    #[new(default)]
+    audio_source_params: Option<AudioParams>,
+    // Kept across inits, the device stays open when switching inputs.
+    #[new(default)]
+    audio_device_params: Option<AudioParams>,
+    #[new(default)]
+    open_latency: Option<Duration>,
+    #[new(default)]
+    stats_counters: Arc<StatsCounters>,
+    #[new(value = "Rational(1, 1000)")]
+    time_base: Rational,
+    // Shared with the video decoder threads, which hold off decoding while paused.
+    #[new(default)]
+    paused: Arc<AtomicBool>,
+    // Shared with the threads, which wait for the restart at the end instead of quitting.
+    #[new(default)]
+    looping: Arc<AtomicBool>,
+    #[new(value = "1.0")]
+    speed: f64,
+    // Serial of a seek_and_pause() whose target frame was not presented yet.
+    #[new(default)]
+    refresh_serial: Option<u64>,
+    // Frame rate if the stream has a constant frame rate.
+    #[new(default)]
+    constant_frame_rate: Option<Rational>,
+    #[new(value = "Rational(0, 1)")]
+    average_frame_rate: Rational,
+    #[new(default)]
+    start_time_ms: u64,
+    #[new(default)]
+    presented_serial: Option<u64>,
+    #[new(default)]
+    presented_frame_index: Option<u64>,
+    #[new(default)]
+    presented_frame_time: Option<u64>,
+    // Frame time in ms of the last presented or delivered frame, shared with the delivery
+    // thread.
+    #[new(default)]
+    position_ms: Arc<AtomicU64>,
+    #[new(default)]
+    repeated_frames: u64,
+    #[new(default)]
+    dropped_frames: u64,
+    #[new(default)]
+    displayed_frames: u64,
+    #[new(default)]
+    duration: Option<Duration>,
+    #[new(default)]
+    is_live: bool,
+    #[new(default)]
+    rotation: u32,
+    #[new(value = "Rational(1, 1)")]
+    sample_aspect_ratio: Rational,
+    #[new(value = "color::Range::Unspecified")]
+    color_range: color::Range,
+    #[new(value = "color::Space::Unspecified")]
+    color_space: color::Space,
+    #[new(default)]
+    frame_cache: Option<Arc<FrameCache>>,
+    #[new(value = "DurationSource::Unknown")]
+    duration_source: DurationSource,
+    #[new(default)]
+    event_sender: Option<mpsc::Sender<DecoderEvent>>,
+    #[new(default)]
+    event_receiver: Option<mpsc::Receiver<DecoderEvent>>,
+    #[new(default)]
+    error_sender: Option<mpsc::Sender<Report<FileDecoderError>>>,
+    #[new(default)]
+    error_receiver: Option<mpsc::Receiver<Report<FileDecoderError>>>,
+    // Serial and target of the seek whose first frame was not presented yet.
+    #[new(default)]
+    pending_seek: Option<(u64, Duration)>,
+}
+
+/// Stream of an input, see [`FileDecoder::list_streams`].
+#[derive(Clone, Debug)]
+pub struct StreamInfo {
+    pub index: usize,
+    /// e.g. "h264"
+    pub codec_name: String,
+    pub medium: Type,
+}
+
+/// Properties of the main video stream, see [`FileDecoder::media_info`].
+#[derive(Clone, Debug)]
+pub struct MediaInfo {
+    /// e.g. "h264"
+    pub codec_name: String,
+    /// Size of the decoded frames, before scaling to [`FileDecoderBuilder::output_size`].
+    pub width: u32,
+    pub height: u32,
+    /// Pixel format of the decoder, the frames can be converted to another one, see
+    /// [`FileDecoder::pixel_format`].
+    pub pixel_format: Pixel,
+    /// Average frame rate, 0/1 if unknown.
+    pub frame_rate: Rational,
+    /// Bitrate as declared by the stream or container in kbit/s.
+    pub bitrate_kbps: Option<u64>,
+}
+
+/// Sample rate, sample format and channels of audio, see [`AudioSpec`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioParams {
+    pub sample_rate: u32,
+    pub sample_format: format::Sample,
+    pub channels: u16,
+}
+
+/// How the audio is converted on its way to the device, see [`FileDecoder::audio_spec`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioSpec {
+    /// The audio stream as the decoder reports it.
+    pub source: AudioParams,
+    /// The audio in the [`AudioQueue`], the source is resampled to it.
+    pub output: AudioParams,
+    /// The audio device as opened by the consumer, `None` until it reports it with
+    /// [`FileDecoder::set_audio_device_params`].
+    pub device: Option<AudioParams>,
+}
+
+/// Hardware decoding API, see [`FileDecoderBuilder::hwaccel`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HwAccel {
+    /// VA-API on Linux.
+    Vaapi,
+    /// NVDEC on NVIDIA GPUs.
+    Nvdec,
+    /// VideoToolbox on macOS.
+    VideoToolbox,
+}
+
+impl HwAccel {
+    fn device_type(self) -> ffmpeg_rs::ffi::AVHWDeviceType {
+        match self {
+            HwAccel::Vaapi => ffmpeg_rs::ffi::AVHWDeviceType::AV_HWDEVICE_TYPE_VAAPI,
+            HwAccel::Nvdec => ffmpeg_rs::ffi::AVHWDeviceType::AV_HWDEVICE_TYPE_CUDA,
+            HwAccel::VideoToolbox => ffmpeg_rs::ffi::AVHWDeviceType::AV_HWDEVICE_TYPE_VIDEOTOOLBOX,
+        }
+    }
+}
+
+/// Mapping of the volume to the gain of the audio, see [`FileDecoderBuilder::volume_curve`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VolumeCurve {
+    /// The gain is proportional to the volume.
+    Linear,
+    /// Every volume step changes the gain by the same number of dB, from
+    /// -[`FileDecoder::VOLUME_RANGE_DB`] dB at volume 1 to 0 dB at
+    /// [`FileDecoder::MAX_VOLUME`]. Volume 0 is silent. Sounds evenly spaced to the ear.
+    Logarithmic,
+}
+
+/// Upper bound of the frame cache, see [`FileDecoderBuilder::frame_cache`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameCacheLimit {
+    /// Number of frames.
+    Frames(usize),
+    /// Size of the frame data in bytes.
+    Bytes(usize),
+}
+
+/// Result of [`FileDecoder::step_back`].
+pub enum BackwardStep {
+    /// The previous frame came from the frame cache, present it directly.
+    Cached(VideoData),
+    /// The previous frame was not cached, a seek to it with this serial was started.
+    Seeking(u64),
+}
+
+/// Recently decoded frames of the main video stream, cleared on every serial change.
+struct FrameCache {
+    limit: FrameCacheLimit,
+    frames: Mutex<VecDeque<VideoData>>,
+}
+
+impl FrameCache {
+    fn new(limit: FrameCacheLimit) -> FrameCache {
+        FrameCache {
+            limit,
+            frames: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn push(&self, video_data: VideoData) {
+        let mut frames = self.frames.lock().unwrap();
+        frames.push_back(video_data);
+        match self.limit {
+            FrameCacheLimit::Frames(max_frames) => {
+                while frames.len() > max_frames {
+                    frames.pop_front();
+                }
+            }
+            FrameCacheLimit::Bytes(max_bytes) => {
+                let frame_size = |video_data: &VideoData| {
+                    let frame = &video_data.video_frame;
+                    (0..frame.planes())
+                        .map(|plane| frame.data(plane).len())
+                        .sum::<usize>()
+                };
+                let mut bytes: usize = frames.iter().map(frame_size).sum();
+                while bytes > max_bytes {
+                    match frames.pop_front() {
+                        Some(video_data) => bytes -= frame_size(&video_data),
+                        None => break,
+                    }
+                }
+            }
+        }
+    }
+
+    fn clear(&self) {
+        self.frames.lock().unwrap().clear();
+    }
+
+    // Newest cached frame of `serial` before `frame_time`.
+    fn frame_before(&self, serial: u64, frame_time: u64) -> Option<VideoData> {
+        self.frames
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|video_data| video_data.serial == serial && video_data.frame_time < frame_time)
+            .cloned()
+    }
+}
+
+/// Where [`FileDecoder::duration`] comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DurationSource {
+    /// Declared by the container.
+    Container,
+    /// Declared by the video stream, if the container does not declare it.
+    Stream,
+    /// Set via [`FileDecoderBuilder::duration_override`].
+    Override,
+    /// Estimated from the last packet, see [`FileDecoderBuilder::estimate_duration`].
+    Estimated,
+    /// Not known.
+    Unknown,
+}
+
+/// Additional video stream decoded next to the main one, see
+/// [`FileDecoderBuilder::extra_video_streams`].
+struct ExtraVideoStream {
+    stream_index: usize,
+    packet_queue: PacketQueue,
+    video_queue: VideoQueue,
+    width: u32,
+    height: u32,
 }
 
 #[derive(new)]
 #[allow(clippy::too_many_arguments)]
 struct DemuxerData {
     stream: ffmpeg_rs::format::context::Input,
+    // Index of the main video stream, the measured bitrate refers to it.
     stream_index: usize,
     time_base: Rational,
-    #[new(value = "0")]
     seek_serial: u64,
-    packet_queue: PacketQueue,
-    running: Weak<bool>,
-    seek_receiver: mpsc::Receiver<i64>,
+    trim_start: Option<Duration>,
+    seek_flags: SeekFlags,
+    // Stream index and packet queue of every decoded stream.
+    packet_queues: Vec<(usize, PacketQueue)>,
+    running: Arc<AtomicBool>,
+    looping: Arc<AtomicBool>,
+    seek_receiver: mpsc::Receiver<DemuxerSeek>,
     serial_receiver: mpsc::Receiver<u64>,
+    stats_counters: Arc<StatsCounters>,
 }
 
 #[derive(new)]
-struct DecoderData {
-    pixel_format: Pixel,
-    decoder: ffmpeg_rs::decoder::Video,
+struct AudioDecoderData {
+    decoder: ffmpeg_rs::decoder::Audio,
     time_base: Rational,
     packet_queue: PacketQueue,
+    audio_queue: AudioQueue,
+    volume: Arc<AtomicU32>,
+    volume_curve: VolumeCurve,
+    muted: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    looping: Arc<AtomicBool>,
+    seek_serial: u64,
+    #[new(default)]
+    skip_until: Option<u64>,
+    serial_receiver: mpsc::Receiver<DecoderSerial>,
+}
+
+#[derive(new)]
+struct SubtitleDecoderData {
+    decoder: ffmpeg_rs::decoder::Subtitle,
+    time_base: Rational,
+    packet_queue: PacketQueue,
+    subtitle_queue: SubtitleQueue,
+    running: Arc<AtomicBool>,
+    looping: Arc<AtomicBool>,
+    seek_serial: u64,
+    serial_receiver: mpsc::Receiver<DecoderSerial>,
+}
+
+#[derive(new)]
+#[allow(clippy::too_many_arguments)]
+struct DecoderData {
+    // Only the decoder of the main video stream reports events and seek latency.
+    primary: bool,
+    pixel_format: Pixel,
+    decoder: ffmpeg_rs::decoder::Video,
+    time_base: Rational,
+    trim_end: Option<Duration>,
+    output_fps: Option<f64>,
+    input_color_range: Option<color::Range>,
+    output_color_range: Option<color::Range>,
+    burn_in_timestamps: bool,
+    // Size to scale the frames to, `None` for the native size.
+    output_size: Option<(u32, u32)>,
+    scaler_flags: Flags,
+    // Image adjustment of the main video stream.
+    eq: Option<Arc<SharedEq>>,
+    // Deinterlace the frames flagged as interlaced, only for the main video stream.
+    deinterlace: bool,
+    // Filter burning in the subtitles, only for the main video stream.
+    subtitle_filter: Option<String>,
+    keep_hw_frames: bool,
+    frame_cache: Option<Arc<FrameCache>>,
+    packet_queue: PacketQueue,
     video_queue: VideoQueue,
-    running: Weak<bool>,
-    #[new(value = "0")]
+    running: Arc<AtomicBool>,
+    looping: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    stats_counters: Arc<StatsCounters>,
+    event_sender: mpsc::Sender<DecoderEvent>,
     seek_serial: u64,
-    serial_receiver: mpsc::Receiver<u64>,
+    #[new(default)]
+    skip_until: Option<u64>,
+    serial_receiver: mpsc::Receiver<DecoderSerial>,
+}
+
+/// Seek request sent to the demuxer thread.
+#[derive(new)]
+struct DemuxerSeek {
+    // Target in the time base of the stream.
+    seek_to: i64,
+    keyframe: KeyframeSearch,
+}
+
+/// Keyframe the demuxer lands on relative to the seek target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum KeyframeSearch {
+    /// As configured via [`FileDecoderBuilder::seek_flags`].
+    Default,
+    /// Last keyframe at or before the target.
+    AtOrBefore,
+    /// First keyframe at or after the target.
+    AtOrAfter,
+}
+
+/// Serial change sent to the decoder thread on seek.
+#[derive(new)]
+struct DecoderSerial {
+    serial: u64,
+    /// Frames with a frame time (in ms) before this are dropped after the seek.
+    skip_until: Option<u64>,
+}
+
+/// State of the frames leaving the decoder thread, reset on every serial change.
+#[derive(Default)]
+struct OutputState {
+    // Serial change caused by a seek, which did not produce a frame yet.
+    seeking: bool,
+    // No frame with the serial was queued yet.
+    landing: bool,
+    // Index of the next frame queued with the serial.
+    frame_index: u64,
+    // Last frame dropped before the skip_until target, queued if the target is past the end.
+    last_skipped: Option<DecodedFrame>,
+    last_frame_time: Option<u64>,
+    // Next point in time (ms) on the output_fps grid to deliver a frame for.
+    next_output_time: Option<f64>,
+}
+
+#[derive(new)]
+struct DecodedFrame {
+    timestamp: i64,
+    frame: Video,
+    color_primaries: color::Primaries,
+    color_transfer_characteristic: color::TransferCharacteristic,
+    color_space: color::Space,
+    color_range: color::Range,
+    keyframe: bool,
 }
 
 #[derive(new)]
@@ -132,152 +1068,1335 @@ struct PacketData {
     packet: Packet,
 }
 
-#[derive(new)]
+#[derive(new, Clone)]
 pub struct VideoData {
     pub serial: u64,
     pub frame_time: u64,
     pub diff_to_prev_frame: u64,
+    /// Timestamp of the decoded frame in the time base of the stream, see
+    /// [`FileDecoder::time_base`].
+    pub pts: i64,
+    /// Number of the frame since the serial changed, counting from 0.
+    pub frame_index: u64,
     pub video_frame: Video,
+    /// Color metadata of the decoded frame, the pixels are not converted accordingly.
+    pub color_primaries: color::Primaries,
+    pub color_transfer_characteristic: color::TransferCharacteristic,
+    pub color_space: color::Space,
+    pub color_range: color::Range,
+    /// True if the frame is a keyframe (I-frame).
+    pub keyframe: bool,
+    /// True for the first frame with a new serial, e.g. where a seek landed. Its frame time
+    /// is the actual position, which can be before the requested one.
+    pub is_seek_landing: bool,
+    /// The frame in GPU memory, see [`FileDecoderBuilder::keep_hw_frames`].
+    #[new(default)]
+    pub hw_frame: Option<HwFrame>,
+}
+
+/// Reference to a frame in GPU memory, e.g. a VA-API surface in `data[3]` of the frame.
+/// Cloning adds a reference to the same surface.
+pub struct HwFrame(Video);
+
+impl HwFrame {
+    pub fn frame(&self) -> &Video {
+        &self.0
+    }
+
+    /// Copy the frame to system memory, in the format the hardware decoder outputs, e.g.
+    /// NV12.
+    pub fn download(&self) -> Result<Video, FileDecoderError> {
+        FileDecoder::download_frame(FileDecoder::ref_frame(&self.0))
+    }
+}
+
+impl Clone for HwFrame {
+    fn clone(&self) -> HwFrame {
+        HwFrame(FileDecoder::ref_frame(&self.0))
+    }
+}
+
+/// Decoded audio resampled to [`FileDecoder::AUDIO_SAMPLE_RATE`] and
+/// [`FileDecoder::AUDIO_CHANNELS`] channels.
+pub struct AudioData {
+    pub serial: u64,
+    pub frame_time: u64,
+    /// Interleaved signed 16 bit samples.
+    pub samples: Vec<i16>,
+}
+
+/// Brightness, contrast and saturation adjustment with FFmpeg's eq filter, see
+/// [`FileDecoder::set_eq`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EqSettings {
+    /// -1.0 to 1.0, 0.0 keeps the brightness.
+    pub brightness: f64,
+    /// -2.0 to 2.0, 1.0 keeps the contrast.
+    pub contrast: f64,
+    /// 0.0 (gray) to 3.0, 1.0 keeps the saturation.
+    pub saturation: f64,
+}
+
+impl Default for EqSettings {
+    fn default() -> EqSettings {
+        EqSettings {
+            brightness: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+impl EqSettings {
+    fn clamped(self) -> EqSettings {
+        EqSettings {
+            brightness: self.brightness.clamp(-1.0, 1.0),
+            contrast: self.contrast.clamp(-2.0, 2.0),
+            saturation: self.saturation.clamp(0.0, 3.0),
+        }
+    }
+}
+
+// EqSettings shared with the decoder thread, the values are stored as f64 bits.
+struct SharedEq {
+    brightness: AtomicU64,
+    contrast: AtomicU64,
+    saturation: AtomicU64,
+    // Set with every change, the decoder thread passes the new values on to the filter.
+    changed: AtomicBool,
+}
+
+impl Default for SharedEq {
+    fn default() -> SharedEq {
+        let settings = EqSettings::default();
+        SharedEq {
+            brightness: AtomicU64::new(settings.brightness.to_bits()),
+            contrast: AtomicU64::new(settings.contrast.to_bits()),
+            saturation: AtomicU64::new(settings.saturation.to_bits()),
+            changed: AtomicBool::new(false),
+        }
+    }
+}
+
+impl SharedEq {
+    fn load(&self) -> EqSettings {
+        EqSettings {
+            brightness: f64::from_bits(self.brightness.load(Ordering::Relaxed)),
+            contrast: f64::from_bits(self.contrast.load(Ordering::Relaxed)),
+            saturation: f64::from_bits(self.saturation.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn store(&self, settings: EqSettings) {
+        self.brightness
+            .store(settings.brightness.to_bits(), Ordering::Relaxed);
+        self.contrast
+            .store(settings.contrast.to_bits(), Ordering::Relaxed);
+        self.saturation
+            .store(settings.saturation.to_bits(), Ordering::Relaxed);
+        self.changed.store(true, Ordering::Release);
+    }
+}
+
+/// Subtitle text, shown from `start_time` until `end_time` (frame times in ms like
+/// [`VideoData::frame_time`]).
+#[derive(Clone, Debug)]
+pub struct SubtitleData {
+    pub serial: u64,
+    pub start_time: u64,
+    /// `None` if the stream does not tell, the next subtitle replaces it then.
+    pub end_time: Option<u64>,
+    /// The lines of the subtitle, without the formatting of ASS subtitles.
+    pub text: String,
+}
+
+impl AudioData {
+    /// The samples for playback at `speed` (see [`FileDecoder::set_speed`]). Samples are
+    /// dropped (faster) or duplicated (slower) evenly, which is cheap but shifts the pitch
+    /// with the speed like a tape. There is no pitch preserving time stretching (atempo).
+    pub fn samples_at_speed(&self, speed: f64) -> Vec<i16> {
+        let channels = FileDecoder::AUDIO_CHANNELS as usize;
+        let frames = self.samples.len() / channels;
+        if speed == 1.0 || frames == 0 {
+            return self.samples.clone();
+        }
+        let output_frames = (frames as f64 / speed).round() as usize;
+        let mut samples = Vec::with_capacity(output_frames * channels);
+        for frame in 0..output_frames {
+            let source = ((frame as f64 * speed) as usize).min(frames - 1) * channels;
+            samples.extend_from_slice(&self.samples[source..source + channels]);
+        }
+        samples
+    }
 }
 
 impl FileDecoder {
+    /// Sample rate of the audio in the [`AudioQueue`].
+    pub const AUDIO_SAMPLE_RATE: u32 = 48000;
+    /// Channels of the audio in the [`AudioQueue`].
+    pub const AUDIO_CHANNELS: u16 = 2;
     const PACKET_QUEUE_SIZE: usize = 60;
     const FRAME_QUEUE_SIZE: usize = 3;
+    // About one second of audio with typical frame sizes.
+    const AUDIO_QUEUE_SIZE: usize = 50;
+    // Subtitles are sparse, the consumer takes them as soon as they are queued.
+    const SUBTITLE_QUEUE_SIZE: usize = 16;
+    // Interval in which waiting threads check for a stop or the loop restart.
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+    /// Range of the playback speed, see [`FileDecoder::set_speed`].
+    pub const MIN_SPEED: f64 = 0.25;
+    pub const MAX_SPEED: f64 = 4.0;
+    /// Volume of the unchanged audio, see [`FileDecoder::set_volume`].
+    pub const MAX_VOLUME: u32 = 100;
+    /// Attenuation (in dB) of the lowest audible volume with [`VolumeCurve::Logarithmic`].
+    pub const VOLUME_RANGE_DB: f64 = 60.0;
+    const BITRATE_WINDOW_MS: i64 = 1000;
+    // Seeks to a chapter land on the keyframe before it, positions this close to a chapter
+    // start count as being at it.
+    const CHAPTER_TOLERANCE: Duration = Duration::from_secs(2);
 
     pub fn init(&mut self) -> Result<(), FileDecoderError> {
-        ffmpeg_rs::init()
-            .into_report()
-            .attach_printable("FFmpeg init failed")
-            .change_context(FileDecoderError)?;
-        let input = input(&Path::new(&self.uri))
-            .into_report()
-            .attach_printable("Cannot open file")
-            .change_context(FileDecoderError)?;
-        let video_stream_input = input
+        let init_start = Instant::now();
+        FileDecoder::init_ffmpeg()?;
+        let is_network = self.uri.contains("://");
+        if is_network {
+            static NETWORK_INIT: Once = Once::new();
+            NETWORK_INIT.call_once(format::network::init);
+        }
+        let is_manifest = FileDecoder::is_manifest(&self.uri);
+        let mut options = Dictionary::new();
+        if is_manifest {
+            // Probing the segments of HLS/DASH streams needs more data than a single file.
+            options.set("probesize", "10000000");
+            options.set("analyzeduration", "10000000");
+        }
+        if let (true, Some(open_timeout)) = (is_network, self.open_timeout) {
+            // In microseconds. rw_timeout applies to every protocol, RTSP has its own socket
+            // timeout (stimeout before FFmpeg 5).
+            let timeout = open_timeout.as_micros().to_string();
+            options.set("rw_timeout", &timeout);
+            if self.uri.starts_with("rtsp") {
+                options.set("timeout", &timeout);
+            }
+        }
+        let mut input = if is_manifest || self.concat_files.is_empty() {
+            let opened = format::input_with_dictionary(&self.uri, options)
+                .into_report()
+                .attach_printable(format!("Cannot open {}", self.uri));
+            if is_network {
+                opened
+                    .change_context(ConnectionError)
+                    .change_context(FileDecoderError)?
+            } else {
+                opened.change_context(FileDecoderError)?
+            }
+        } else {
+            FileDecoder::open_concat_input(&self.concat_files)?
+        };
+
+        // Embedded cover art (e.g. in MP3/FLAC) shows up as a single frame video stream
+        // flagged as attached picture, keep its image but do not play it as the main video.
+        self.cover_art = input
             .streams()
-            .best(Type::Video)
-            .ok_or(ffmpeg_rs::Error::StreamNotFound)
-            .into_report()
-            .attach_printable("Could not open video stream")
-            .change_context(FileDecoderError)?;
+            .find(|stream| stream.disposition().contains(Disposition::ATTACHED_PIC))
+            .and_then(|stream| {
+                let attached_pic = unsafe { &(*stream.as_ptr()).attached_pic };
+                if attached_pic.data.is_null() || attached_pic.size <= 0 {
+                    return None;
+                }
+                Some(unsafe {
+                    slice::from_raw_parts(attached_pic.data, attached_pic.size as usize).to_vec()
+                })
+            });
+
+        self.chapters = input
+            .chapters()
+            .map(|chapter| {
+                let start = chapter.start().max(0).rescale_with(
+                    chapter.time_base(),
+                    Rational(1, 1000),
+                    Rounding::Zero,
+                );
+                let title = chapter
+                    .metadata()
+                    .get("title")
+                    .unwrap_or_default()
+                    .to_owned();
+                (Duration::from_millis(start as u64), title)
+            })
+            .collect();
+        self.chapters.sort_by_key(|(start, _)| *start);
+        debug!("{} chapters", self.chapters.len());
+
+        let video_stream_input = match self.video_stream_index {
+            Some(stream_index) => input
+                .stream(stream_index)
+                .filter(|stream| stream.parameters().medium() == Type::Video)
+                .ok_or(ffmpeg_rs::Error::StreamNotFound)
+                .into_report()
+                .attach_printable(format!("Stream {} is no video stream", stream_index))
+                .change_context(FileDecoderError)?,
+            None => FileDecoder::best_video_stream(&input)?,
+        };
         let video_stream_index = video_stream_input.index();
         let video_stream_tb = video_stream_input.time_base();
+        let stream_duration = video_stream_input.duration();
+
+        self.rotation = FileDecoder::display_rotation(&video_stream_input);
+        if self.rotation != 0 {
+            debug!("display rotated by {} degrees", self.rotation);
+        }
 
-        let context_decoder =
-            ffmpeg_rs::codec::context::Context::from_parameters(video_stream_input.parameters())
+        self.average_frame_rate = video_stream_input.avg_frame_rate();
+        self.constant_frame_rate = if self.average_frame_rate.numerator() > 0
+            && self.average_frame_rate == video_stream_input.rate()
+        {
+            Some(self.average_frame_rate)
+        } else {
+            None
+        };
+        let start_time = video_stream_input.start_time();
+        self.start_time_ms = if start_time == ffmpeg_rs::ffi::AV_NOPTS_VALUE || start_time < 0 {
+            0
+        } else {
+            start_time.rescale_with(video_stream_tb, Rational(1, 1000), Rounding::Zero) as u64
+        };
+        self.position_ms
+            .store(self.start_time_ms, Ordering::Relaxed);
+
+        let codec_name = video_stream_input.parameters().id().name().to_owned();
+        let stream_bit_rate = unsafe { (*video_stream_input.parameters().as_ptr()).bit_rate };
+        let declared_bit_rate = if stream_bit_rate > 0 {
+            stream_bit_rate
+        } else {
+            input.bit_rate()
+        };
+        self.declared_bitrate_kbps = if declared_bit_rate > 0 {
+            Some(declared_bit_rate as u64 / 1000)
+        } else {
+            None
+        };
+
+        let decoder = FileDecoder::create_decoder(
+            &video_stream_input,
+            self.lowres,
+            self.hwaccel,
+            self.keyframes_only,
+        )?;
+
+        let mut extra_decoders = Vec::new();
+        for (n, stream_index) in self.extra_video_streams.iter().enumerate() {
+            let stream = input
+                .stream(*stream_index)
+                .filter(|stream| {
+                    stream.index() != video_stream_index
+                        && stream.parameters().medium() == Type::Video
+                })
+                .ok_or(ffmpeg_rs::Error::StreamNotFound)
                 .into_report()
-                .attach_printable("Cannot create context from parameters")
+                .attach_printable(format!("Stream {} is no extra video stream", stream_index))
                 .change_context(FileDecoderError)?;
+            let extra_decoder = FileDecoder::create_decoder(
+                &stream,
+                self.lowres,
+                self.hwaccel,
+                self.keyframes_only,
+            )?;
+            if self.extra_streams.len() <= n {
+                self.extra_streams.push(ExtraVideoStream {
+                    stream_index: *stream_index,
+                    packet_queue: Arc::new(BlockingDelayQueue::new_with_capacity(
+                        self.packet_queue_capacity,
+                    )),
+                    video_queue: Arc::new(BlockingDelayQueue::new_with_capacity(
+                        self.frame_queue_capacity,
+                    )),
+                    width: 0,
+                    height: 0,
+                });
+            }
+            let extra_stream = &mut self.extra_streams[n];
+            extra_stream.stream_index = *stream_index;
+            extra_stream.width = extra_decoder.width();
+            extra_stream.height = extra_decoder.height();
+            extra_decoders.push((extra_decoder, stream.time_base()));
+        }
 
-        let decoder = context_decoder
-            .decoder()
-            .video()
-            .into_report()
-            .attach_printable("Cannot create decoder")
-            .change_context(FileDecoderError)?;
+        // Audio is optional, the video plays silently without a decodable audio stream.
+        let audio_stream = if self.disable_audio {
+            debug!("audio disabled");
+            None
+        } else {
+            input.streams().best(Type::Audio)
+        };
+        let audio_decoder =
+            audio_stream.and_then(|stream| match FileDecoder::create_audio_decoder(&stream) {
+                Ok(audio_decoder) => Some((stream.index(), stream.time_base(), audio_decoder)),
+                Err(err) => {
+                    warn!("cannot decode audio stream {}: {:?}", stream.index(), err);
+                    None
+                }
+            });
+        self.has_audio = audio_decoder.is_some();
+        self.audio_source_params =
+            audio_decoder
+                .as_ref()
+                .map(|(_, _, audio_decoder)| AudioParams {
+                    sample_rate: audio_decoder.rate(),
+                    sample_format: audio_decoder.format(),
+                    channels: audio_decoder.channels(),
+                });
+
+        let subtitle_stream = match self.subtitle_stream_index {
+            Some(stream_index) => input
+                .stream(stream_index)
+                .filter(|stream| stream.parameters().medium() == Type::Subtitle)
+                .or_else(|| {
+                    warn!("stream {} is no subtitle stream", stream_index);
+                    None
+                }),
+            None if self.subtitles || self.burn_in_subtitles => {
+                input.streams().best(Type::Subtitle)
+            }
+            None => None,
+        };
+        self.subtitle_filter = None;
+        let subtitle_stream = match subtitle_stream {
+            Some(stream) if self.burn_in_subtitles => {
+                if !self.concat_files.is_empty() {
+                    warn!("cannot burn in the subtitles of concatenated files");
+                } else if ffmpeg_rs::filter::find("subtitles").is_none() {
+                    warn!("FFmpeg has no subtitles filter, cannot burn in the subtitles");
+                } else {
+                    // The filter counts the subtitle streams only.
+                    let subtitle_index = input
+                        .streams()
+                        .filter(|other| other.parameters().medium() == Type::Subtitle)
+                        .position(|other| other.index() == stream.index())
+                        .unwrap_or_default();
+                    self.subtitle_filter =
+                        Some(FileDecoder::subtitle_filter(&self.uri, subtitle_index));
+                }
+                // The filter reads the subtitles itself.
+                None
+            }
+            subtitle_stream => subtitle_stream,
+        };
+        let subtitle_decoder = subtitle_stream.and_then(|stream| {
+            match FileDecoder::create_subtitle_decoder(&stream) {
+                Ok(subtitle_decoder) => {
+                    Some((stream.index(), stream.time_base(), subtitle_decoder))
+                }
+                Err(err) => {
+                    warn!(
+                        "cannot decode subtitle stream {}: {:?}",
+                        stream.index(),
+                        err
+                    );
+                    None
+                }
+            }
+        });
+        self.has_subtitles = subtitle_decoder.is_some();
+
+        let container_duration = input.duration();
+        // Live manifests have no duration, the playlist grows while playing.
+        self.is_live = is_manifest
+            && (container_duration == ffmpeg_rs::ffi::AV_NOPTS_VALUE || container_duration <= 0);
+        if self.is_live {
+            debug!("{} is a live stream", self.uri);
+        }
+        (self.duration, self.duration_source) = if let Some(duration) = self.duration_override {
+            (Some(duration), DurationSource::Override)
+        } else if self.estimate_duration {
+            let start_time = if start_time == ffmpeg_rs::ffi::AV_NOPTS_VALUE || start_time < 0 {
+                0
+            } else {
+                start_time
+            };
+            match FileDecoder::estimate_duration(
+                &mut input,
+                video_stream_index,
+                video_stream_tb,
+                start_time,
+            )? {
+                Some(duration) => (Some(duration), DurationSource::Estimated),
+                None => (None, DurationSource::Unknown),
+            }
+        } else if !self.ignore_duration
+            && container_duration != ffmpeg_rs::ffi::AV_NOPTS_VALUE
+            && container_duration > 0
+        {
+            (
+                Some(Duration::from_micros(container_duration as u64)),
+                DurationSource::Container,
+            )
+        } else if !self.ignore_duration
+            && stream_duration != ffmpeg_rs::ffi::AV_NOPTS_VALUE
+            && stream_duration > 0
+        {
+            let duration_ms =
+                stream_duration.rescale_with(video_stream_tb, Rational(1, 1000), Rounding::Zero);
+            (
+                Some(Duration::from_millis(duration_ms as u64)),
+                DurationSource::Stream,
+            )
+        } else {
+            (None, DurationSource::Unknown)
+        };
+        debug!(
+            "duration {:?} from {:?}",
+            self.duration, self.duration_source
+        );
+
+        if self.frame_cache.is_none() {
+            self.frame_cache = self
+                .frame_cache_limit
+                .map(|limit| Arc::new(FrameCache::new(limit)));
+        }
+
+        if self.event_sender.is_none() {
+            let (event_sender, event_receiver) = channel();
+            self.event_sender = Some(event_sender);
+            self.event_receiver = Some(event_receiver);
+        }
+        if self.error_sender.is_none() {
+            let (error_sender, error_receiver) = channel();
+            self.error_sender = Some(error_sender);
+            self.error_receiver = Some(error_receiver);
+        }
 
-        let running = Arc::new(true);
+        let running = Arc::new(AtomicBool::new(true));
 
-        let (demuxer_seek_sender, demuxer_seek_receiver): (mpsc::Sender<i64>, mpsc::Receiver<i64>) =
-            channel();
+        let (demuxer_seek_sender, demuxer_seek_receiver): (
+            mpsc::Sender<DemuxerSeek>,
+            mpsc::Receiver<DemuxerSeek>,
+        ) = channel();
         let (demuxer_serial_sender, demuxer_serial_receiver): (
             mpsc::Sender<u64>,
             mpsc::Receiver<u64>,
         ) = channel();
         let (decoder_serial_sender, decoder_serial_receiver): (
-            mpsc::Sender<u64>,
-            mpsc::Receiver<u64>,
+            mpsc::Sender<DecoderSerial>,
+            mpsc::Receiver<DecoderSerial>,
         ) = channel();
 
         self.demuxer_seek_sender = Some(demuxer_seek_sender);
         self.demuxer_serial_sender = Some(demuxer_serial_sender);
-        self.decoder_serial_sender = Some(decoder_serial_sender);
+        self.decoder_serial_senders = vec![decoder_serial_sender];
 
         let packet_queue = self.packet_queue.clone();
+        let mut packet_queues = vec![(video_stream_index, packet_queue.clone())];
+        packet_queues.extend(
+            self.extra_streams
+                .iter()
+                .map(|extra_stream| (extra_stream.stream_index, extra_stream.packet_queue.clone())),
+        );
+        if let Some((audio_stream_index, _, _)) = &audio_decoder {
+            packet_queues.push((*audio_stream_index, self.audio_packet_queue.clone()));
+        }
+        if let Some((subtitle_stream_index, _, _)) = &subtitle_decoder {
+            packet_queues.push((*subtitle_stream_index, self.subtitle_packet_queue.clone()));
+        }
         self.demuxer_data.replace(DemuxerData::new(
             input,
             video_stream_index,
             video_stream_tb,
-            packet_queue.clone(),
-            Arc::downgrade(&running),
+            self.seek_serial,
+            self.trim_start,
+            self.seek_flags,
+            packet_queues,
+            running.clone(),
+            self.looping.clone(),
             demuxer_seek_receiver,
             demuxer_serial_receiver,
+            self.stats_counters.clone(),
         ));
 
         self.width = decoder.width();
         self.height = decoder.height();
+        self.time_base = video_stream_tb;
+        // Unknown (0/1) means square pixels.
+        let sample_aspect_ratio = decoder.aspect_ratio();
+        self.sample_aspect_ratio =
+            if sample_aspect_ratio.numerator() > 0 && sample_aspect_ratio.denominator() > 0 {
+                sample_aspect_ratio
+            } else {
+                Rational(1, 1)
+            };
+        self.output_pixel_format = if self.auto_pixel_formats.contains(&decoder.format()) {
+            debug!("keep pixel format {:?} of the decoder", decoder.format());
+            decoder.format()
+        } else {
+            self.pixel_format
+        };
+        self.color_range = decoder.color_range();
+        self.color_space = decoder.color_space();
+        self.media_info = Some(MediaInfo {
+            codec_name,
+            width: decoder.width(),
+            height: decoder.height(),
+            pixel_format: decoder.format(),
+            frame_rate: self.average_frame_rate,
+            bitrate_kbps: self.declared_bitrate_kbps,
+        });
+        debug!("{:?}", self.media_info);
+        let bit_depth = FileDecoder::bit_depth(decoder.format());
+        if bit_depth > FileDecoder::bit_depth(self.output_pixel_format) {
+            info!(
+                "convert {} bit {:?} to {:?}",
+                bit_depth,
+                decoder.format(),
+                self.output_pixel_format
+            );
+        }
+        if matches!(
+            decoder.color_transfer_characteristic(),
+            color::TransferCharacteristic::SMPTE2084 | color::TransferCharacteristic::ARIB_STD_B67
+        ) {
+            warn!(
+                "HDR ({:?}) not tone-mapped, the colors look washed out",
+                decoder.color_transfer_characteristic()
+            );
+        }
 
         let video_producer_queue = self.video_queue.clone();
-        self.decoder_data.replace(DecoderData::new(
-            self.pixel_format,
+        self.decoder_data.clear();
+        self.decoder_data.push(DecoderData::new(
+            true,
+            self.output_pixel_format,
             decoder,
             video_stream_tb,
+            self.trim_end,
+            self.output_fps,
+            self.input_color_range,
+            self.output_color_range,
+            self.burn_in_timestamps,
+            self.output_size,
+            self.scaler_flags,
+            Some(self.eq.clone()),
+            self.deinterlace,
+            self.subtitle_filter.clone(),
+            self.keep_hw_frames,
+            self.frame_cache.clone(),
             packet_queue,
             video_producer_queue,
-            Arc::downgrade(&running),
+            running.clone(),
+            self.looping.clone(),
+            self.paused.clone(),
+            self.stats_counters.clone(),
+            self.event_sender.clone().unwrap(),
+            self.seek_serial,
             decoder_serial_receiver,
         ));
 
+        for ((extra_decoder, time_base), extra_stream) in
+            extra_decoders.into_iter().zip(self.extra_streams.iter())
+        {
+            let (serial_sender, serial_receiver) = channel();
+            self.decoder_serial_senders.push(serial_sender);
+            self.decoder_data.push(DecoderData::new(
+                false,
+                self.output_pixel_format,
+                extra_decoder,
+                time_base,
+                self.trim_end,
+                self.output_fps,
+                self.input_color_range,
+                self.output_color_range,
+                self.burn_in_timestamps,
+                None,
+                self.scaler_flags,
+                None,
+                false,
+                None,
+                None,
+                self.keep_hw_frames,
+                extra_stream.packet_queue.clone(),
+                extra_stream.video_queue.clone(),
+                running.clone(),
+                self.looping.clone(),
+                self.paused.clone(),
+                self.stats_counters.clone(),
+                self.event_sender.clone().unwrap(),
+                self.seek_serial,
+                serial_receiver,
+            ));
+        }
+
+        self.audio_decoder_data = None;
+        if let Some((_, audio_time_base, audio_decoder)) = audio_decoder {
+            let (serial_sender, serial_receiver) = channel();
+            self.decoder_serial_senders.push(serial_sender);
+            self.audio_decoder_data.replace(AudioDecoderData::new(
+                audio_decoder,
+                audio_time_base,
+                self.audio_packet_queue.clone(),
+                self.audio_queue.clone(),
+                self.volume.clone(),
+                self.volume_curve,
+                self.muted.clone(),
+                running.clone(),
+                self.looping.clone(),
+                self.seek_serial,
+                serial_receiver,
+            ));
+        }
+
+        self.subtitle_decoder_data = None;
+        if let Some((_, subtitle_time_base, subtitle_decoder)) = subtitle_decoder {
+            let (serial_sender, serial_receiver) = channel();
+            self.decoder_serial_senders.push(serial_sender);
+            self.subtitle_decoder_data.replace(SubtitleDecoderData::new(
+                subtitle_decoder,
+                subtitle_time_base,
+                self.subtitle_packet_queue.clone(),
+                self.subtitle_queue.clone(),
+                running.clone(),
+                self.looping.clone(),
+                self.seek_serial,
+                serial_receiver,
+            ));
+        }
+
         self.running.replace(running);
 
+        let open_latency = init_start.elapsed();
+        debug!("opening {} took {:?}", self.uri, open_latency);
+        self.open_latency = Some(open_latency);
+
         Ok(())
     }
 
+    // The stream ffmpeg picks as best, unless it is cover art and there is another video stream.
+    fn best_video_stream(
+        input: &format::context::Input,
+    ) -> Result<format::stream::Stream<'_>, FileDecoderError> {
+        input
+            .streams()
+            .best(Type::Video)
+            .and_then(|best| {
+                if !best.disposition().contains(Disposition::ATTACHED_PIC) {
+                    return Some(best);
+                }
+                input
+                    .streams()
+                    .find(|stream| {
+                        stream.parameters().medium() == Type::Video
+                            && !stream.disposition().contains(Disposition::ATTACHED_PIC)
+                    })
+                    .or_else(|| {
+                        warn!("only found cover art video stream, show it as still image");
+                        Some(best)
+                    })
+            })
+            .ok_or(ffmpeg_rs::Error::StreamNotFound)
+            .into_report()
+            .attach_printable_lazy(|| {
+                let media: Vec<String> = input
+                    .streams()
+                    .map(|stream| format!("{:?}", stream.parameters().medium()))
+                    .collect();
+                format!("The input has no video stream, only {:?}", media)
+            })
+            .change_context(NoVideoStream)
+            .change_context(FileDecoderError)
+    }
+
+    /// List the streams of the input `uri`, e.g. to pick one with
+    /// [`FileDecoderBuilder::video_stream_index`].
+    pub fn list_streams(uri: &str) -> Result<Vec<StreamInfo>, FileDecoderError> {
+        let input = input(&uri)
+            .into_report()
+            .attach_printable(format!("Cannot open {}", uri))
+            .change_context(FileDecoderError)?;
+        Ok(input
+            .streams()
+            .map(|stream| StreamInfo {
+                index: stream.index(),
+                codec_name: stream.parameters().id().name().to_owned(),
+                medium: stream.parameters().medium(),
+            })
+            .collect())
+    }
+
+    // Clockwise rotation in degrees (0, 90, 180 or 270) from the display matrix of the stream,
+    // e.g. of videos recorded in portrait by phones.
+    fn display_rotation(stream: &format::stream::Stream) -> u32 {
+        let display_matrix = stream.side_data().find(|side_data| {
+            side_data.kind() == ffmpeg_rs::codec::packet::side_data::Type::DisplayMatrix
+        });
+        let angle = match display_matrix {
+            Some(side_data) if side_data.data().len() >= 9 * std::mem::size_of::<i32>() => unsafe {
+                ffmpeg_rs::ffi::av_display_rotation_get(side_data.data().as_ptr() as *const i32)
+            },
+            _ => return 0,
+        };
+        if angle.is_nan() {
+            return 0;
+        }
+        // The matrix rotates counterclockwise, snap to multiples of 90 degrees.
+        ((-angle / 90.0).round() as i64).rem_euclid(4) as u32 * 90
+    }
+
+    // HLS (.m3u8) and DASH (.mpd) manifests, ffmpeg's demuxers fetch the segments.
+    fn is_manifest(uri: &str) -> bool {
+        let path = uri.split(['?', '#']).next().unwrap_or_default();
+        let path = path.to_ascii_lowercase();
+        path.ends_with(".m3u8") || path.ends_with(".mpd")
+    }
+
+    fn create_audio_decoder(
+        stream: &format::stream::Stream,
+    ) -> Result<ffmpeg_rs::decoder::Audio, FileDecoderError> {
+        ffmpeg_rs::codec::context::Context::from_parameters(stream.parameters())
+            .into_report()
+            .attach_printable("Cannot create context from parameters")
+            .change_context(FileDecoderError)?
+            .decoder()
+            .audio()
+            .into_report()
+            .attach_printable("Cannot create audio decoder")
+            .change_context(FileDecoderError)
+    }
+
+    fn create_subtitle_decoder(
+        stream: &format::stream::Stream,
+    ) -> Result<ffmpeg_rs::decoder::Subtitle, FileDecoderError> {
+        ffmpeg_rs::codec::context::Context::from_parameters(stream.parameters())
+            .into_report()
+            .attach_printable("Cannot create context from parameters")
+            .change_context(FileDecoderError)?
+            .decoder()
+            .subtitle()
+            .into_report()
+            .attach_printable("Cannot create subtitle decoder")
+            .change_context(FileDecoderError)
+    }
+
+    fn create_decoder(
+        stream: &format::stream::Stream,
+        lowres: u8,
+        hwaccel: Option<HwAccel>,
+        keyframes_only: bool,
+    ) -> Result<ffmpeg_rs::decoder::Video, FileDecoderError> {
+        let mut context_decoder =
+            ffmpeg_rs::codec::context::Context::from_parameters(stream.parameters())
+                .into_report()
+                .attach_printable("Cannot create context from parameters")
+                .change_context(FileDecoderError)?;
+
+        if lowres > 0 {
+            let max_lowres = ffmpeg_rs::decoder::find(context_decoder.id())
+                .map_or(0, |codec| unsafe { (*codec.as_ptr()).max_lowres });
+            if lowres > max_lowres {
+                warn!(
+                    "codec supports lowres up to {}, use it instead of {}",
+                    max_lowres, lowres
+                );
+            }
+            // The decoder reduces its width and height accordingly when it is opened.
+            unsafe {
+                (*context_decoder.as_mut_ptr()).lowres = lowres.min(max_lowres) as i32;
+            }
+        }
+
+        if keyframes_only {
+            unsafe {
+                (*context_decoder.as_mut_ptr()).skip_frame =
+                    ffmpeg_rs::ffi::AVDiscard::AVDISCARD_NONKEY;
+            }
+        }
+
+        if let Some(hwaccel) = hwaccel {
+            match FileDecoder::create_hw_device(context_decoder.id(), hwaccel) {
+                // With a device the default get_format() picks the hardware pixel format, the
+                // codec context owns the device reference from here on.
+                Some(hw_device) => unsafe {
+                    debug!("decode stream {} with {:?}", stream.index(), hwaccel);
+                    (*context_decoder.as_mut_ptr()).hw_device_ctx = hw_device;
+                },
+                None => warn!("{:?} not available, decode in software", hwaccel),
+            }
+        }
+
+        context_decoder
+            .decoder()
+            .video()
+            .into_report()
+            .attach_printable("Cannot create decoder")
+            .change_context(FileDecoderError)
+    }
+
+    fn create_hw_device(
+        codec_id: ffmpeg_rs::codec::Id,
+        hwaccel: HwAccel,
+    ) -> Option<*mut ffmpeg_rs::ffi::AVBufferRef> {
+        let device_type = hwaccel.device_type();
+        let codec = ffmpeg_rs::decoder::find(codec_id)?;
+        let is_supported = (0..)
+            .map(|index| unsafe { ffmpeg_rs::ffi::avcodec_get_hw_config(codec.as_ptr(), index) })
+            .take_while(|config| !config.is_null())
+            .any(|config| unsafe {
+                (*config).device_type == device_type
+                    && (*config).methods
+                        & ffmpeg_rs::ffi::AV_CODEC_HW_CONFIG_METHOD_HW_DEVICE_CTX as i32
+                        != 0
+            });
+        if !is_supported {
+            debug!("codec {:?} does not support {:?}", codec_id, hwaccel);
+            return None;
+        }
+
+        let mut hw_device = std::ptr::null_mut();
+        let ret = unsafe {
+            ffmpeg_rs::ffi::av_hwdevice_ctx_create(
+                &mut hw_device,
+                device_type,
+                std::ptr::null(),
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        if ret < 0 {
+            debug!(
+                "cannot create {:?} device: {}",
+                hwaccel,
+                ffmpeg_rs::Error::from(ret)
+            );
+            return None;
+        }
+        Some(hw_device)
+    }
+
+    // True if the data of `frame` is in GPU memory.
+    fn is_hw_frame(frame: &Video) -> bool {
+        frame.format().descriptor().map_or(false, |descriptor| {
+            unsafe { (*descriptor.as_ptr()).flags }
+            &ffmpeg_rs::ffi::AV_PIX_FMT_FLAG_HWACCEL as u64 != 0
+        })
+    }
+
+    // New reference to the data of `frame`, cloning copies it, which fails for hardware
+    // frames.
+    fn ref_frame(frame: &Video) -> Video {
+        let mut new_ref = Video::empty();
+        unsafe { ffmpeg_rs::ffi::av_frame_ref(new_ref.as_mut_ptr(), frame.as_ptr()) };
+        new_ref
+    }
+
+    /// Copy a frame decoded in hardware to system memory, other frames are returned as is.
+    fn download_frame(frame: Video) -> Result<Video, FileDecoderError> {
+        if !FileDecoder::is_hw_frame(&frame) {
+            return Ok(frame);
+        }
+        let mut sw_frame = Video::empty();
+        let ret = unsafe {
+            ffmpeg_rs::ffi::av_hwframe_transfer_data(sw_frame.as_mut_ptr(), frame.as_ptr(), 0)
+        };
+        if ret < 0 {
+            return Err(Report::new(FileDecoderError).attach_printable(format!(
+                "Cannot transfer hardware frame: {}",
+                ffmpeg_rs::Error::from(ret)
+            )));
+        }
+        // Timestamps, picture type and color properties
+        unsafe { ffmpeg_rs::ffi::av_frame_copy_props(sw_frame.as_mut_ptr(), frame.as_ptr()) };
+        Ok(sw_frame)
+    }
+
+    // Bits per component of `format`, e.g. 10 for P010, 0 if unknown.
+    fn bit_depth(format: Pixel) -> i32 {
+        format.descriptor().map_or(0, |descriptor| unsafe {
+            let descriptor = &*descriptor.as_ptr();
+            descriptor.comp[..descriptor.nb_components as usize]
+                .iter()
+                .map(|component| component.depth)
+                .max()
+                .unwrap_or(0)
+        })
+    }
+
+    // Scaler from `format` in `width` x `height` to `pixel_format` in `output_size`, or the
+    // same size without it.
+    fn create_scaler(
+        format: Pixel,
+        width: u32,
+        height: u32,
+        pixel_format: Pixel,
+        output_size: Option<(u32, u32)>,
+        flags: Flags,
+        input_color_range: Option<color::Range>,
+        output_color_range: Option<color::Range>,
+    ) -> Result<context::Context, FileDecoderError> {
+        let (output_width, output_height) = output_size.unwrap_or((width, height));
+        let mut scaler = context::Context::get(
+            format,
+            width,
+            height,
+            pixel_format,
+            output_width,
+            output_height,
+            flags,
+        )
+        .into_report()
+        .attach_printable("Cannot get scaling context")
+        .change_context(FileDecoderError)?;
+        if input_color_range.is_some() || output_color_range.is_some() {
+            FileDecoder::override_color_range(&mut scaler, input_color_range, output_color_range);
+        }
+        Ok(scaler)
+    }
+
+    fn override_color_range(
+        scaler: &mut context::Context,
+        input: Option<color::Range>,
+        output: Option<color::Range>,
+    ) {
+        let is_full_range = |range: color::Range| i32::from(range == color::Range::JPEG);
+        unsafe {
+            let sws_context = scaler.as_mut_ptr();
+            let mut inv_table: *mut i32 = std::ptr::null_mut();
+            let mut src_range = 0;
+            let mut table: *mut i32 = std::ptr::null_mut();
+            let mut dst_range = 0;
+            let mut brightness = 0;
+            let mut contrast = 0;
+            let mut saturation = 0;
+            let res = ffmpeg_rs::ffi::sws_getColorspaceDetails(
+                sws_context,
+                &mut inv_table,
+                &mut src_range,
+                &mut table,
+                &mut dst_range,
+                &mut brightness,
+                &mut contrast,
+                &mut saturation,
+            );
+            if res < 0 {
+                warn!("scaler does not support color range overrides");
+                return;
+            }
+            let src_range = input.map_or(src_range, is_full_range);
+            let dst_range = output.map_or(dst_range, is_full_range);
+            debug!("override color range to {} -> {}", src_range, dst_range);
+            let res = ffmpeg_rs::ffi::sws_setColorspaceDetails(
+                sws_context,
+                inv_table,
+                src_range,
+                table,
+                dst_range,
+                brightness,
+                contrast,
+                saturation,
+            );
+            if res < 0 {
+                warn!(
+                    "cannot override color range to {} -> {}",
+                    src_range, dst_range
+                );
+            }
+        }
+    }
+
+    // Byte offset of `seek_to` (in `TIME_BASE`) in a file starting at `start_time` with
+    // `bit_rate`, `None` if the bitrate is unknown.
+    fn byte_offset(seek_to: i64, start_time: i64, bit_rate: i64) -> Option<i64> {
+        if bit_rate <= 0 {
+            return None;
+        }
+        let start_time = if start_time == ffmpeg_rs::ffi::AV_NOPTS_VALUE {
+            0
+        } else {
+            start_time
+        };
+        let micros = (seek_to - start_time).max(0) as i128;
+        Some((micros * bit_rate as i128 / 8 / 1_000_000) as i64)
+    }
+
+    // Seek to `seek_to` in `TIME_BASE`.
+    fn seek_input(
+        input: &mut ffmpeg_rs::format::context::Input,
+        seek_to: i64,
+        seek_flags: SeekFlags,
+        keyframe: KeyframeSearch,
+    ) -> std::result::Result<(), ffmpeg_rs::Error> {
+        // Seeking to an adjacent keyframe needs timestamps and must land on a keyframe.
+        let mut seek_flags = match keyframe {
+            KeyframeSearch::Default => seek_flags,
+            _ => SeekFlags::default(),
+        };
+        let mut seek_to = seek_to;
+        if seek_flags.byte {
+            let start_time = unsafe { (*input.as_ptr()).start_time };
+            match FileDecoder::byte_offset(seek_to, start_time, input.bit_rate()) {
+                Some(offset) => {
+                    debug!("seek to byte offset {} for {}", offset, seek_to);
+                    seek_to = offset;
+                }
+                None => {
+                    warn!("bitrate unknown, seek by timestamp instead of byte offset");
+                    seek_flags.byte = false;
+                }
+            }
+        }
+        if seek_flags == SeekFlags::default() && keyframe == KeyframeSearch::Default {
+            return input.seek(seek_to, RangeFull);
+        }
+
+        let mut flags = 0;
+        if seek_flags.any {
+            flags |= ffmpeg_rs::ffi::AVSEEK_FLAG_ANY as i32;
+        }
+        if seek_flags.byte {
+            flags |= ffmpeg_rs::ffi::AVSEEK_FLAG_BYTE as i32;
+        }
+        // avformat_seek_file() ignores AVSEEK_FLAG_BACKWARD, limiting the range does the same.
+        let min_ts = if keyframe == KeyframeSearch::AtOrAfter {
+            seek_to
+        } else {
+            i64::MIN
+        };
+        let max_ts = if seek_flags.backward || keyframe == KeyframeSearch::AtOrBefore {
+            seek_to
+        } else {
+            i64::MAX
+        };
+        let res = unsafe {
+            ffmpeg_rs::ffi::avformat_seek_file(
+                input.as_mut_ptr(),
+                -1,
+                min_ts,
+                seek_to,
+                max_ts,
+                flags,
+            )
+        };
+        if res < 0 {
+            Err(ffmpeg_rs::Error::from(res))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Duration from the end of the last packet of the stream starting at `start_time` (in
+    // `time_base`).
+    fn estimate_duration(
+        input: &mut ffmpeg_rs::format::context::Input,
+        stream_index: usize,
+        time_base: Rational,
+        start_time: i64,
+    ) -> Result<Option<Duration>, FileDecoderError> {
+        let mut end_ms: Option<i64> = None;
+        for (stream, packet) in input.packets() {
+            if stream.index() != stream_index {
+                continue;
+            }
+            if let Some(pts) = packet.pts() {
+                let packet_end = (pts + packet.duration() - start_time).rescale_with(
+                    time_base,
+                    Rational(1, 1000),
+                    Rounding::Zero,
+                );
+                end_ms = Some(end_ms.map_or(packet_end, |end_ms| end_ms.max(packet_end)));
+            }
+        }
+        input
+            .seek(0, RangeFull)
+            .into_report()
+            .attach_printable("Cannot seek back to start after estimating the duration")
+            .change_context(FileDecoderError)?;
+        Ok(end_ms
+            .filter(|end_ms| *end_ms > 0)
+            .map(|end_ms| Duration::from_millis(end_ms as u64)))
+    }
+
+    fn open_concat_input(
+        files: &[String],
+    ) -> Result<ffmpeg_rs::format::context::Input, FileDecoderError> {
+        let mut script = String::from("ffconcat version 1.0\n");
+        for file in files {
+            let path = fs::canonicalize(file)
+                .into_report()
+                .attach_printable(format!("Cannot find concat file {}", file))
+                .change_context(FileDecoderError)?;
+            let path = path.to_string_lossy().replace('\'', "'\\''");
+            script.push_str(&format!("file '{}'\n", path));
+        }
+
+        let script_path = std::env::temp_dir().join(format!(
+            "ffplay-concat-{}-{:p}.ffconcat",
+            std::process::id(),
+            files
+        ));
+        fs::write(&script_path, script)
+            .into_report()
+            .attach_printable("Cannot write concat script")
+            .change_context(FileDecoderError)?;
+
+        let concat_format = unsafe {
+            let name = CString::new("concat").unwrap();
+            let ptr = ffmpeg_rs::ffi::av_find_input_format(name.as_ptr());
+            if ptr.is_null() {
+                None
+            } else {
+                Some(format::Input::wrap(ptr as *mut _))
+            }
+        };
+        let mut options = Dictionary::new();
+        // The script only contains absolute paths which the demuxer rejects in safe mode.
+        options.set("safe", "0");
+        let input = concat_format
+            .ok_or(ffmpeg_rs::Error::DemuxerNotFound)
+            .and_then(|concat_format| {
+                format::open_with(
+                    &script_path,
+                    &ffmpeg_rs::Format::Input(concat_format),
+                    options,
+                )
+            })
+            .map(|context| context.input());
+        // The demuxer reads the whole script when opening, so it is not needed anymore.
+        let _ = fs::remove_file(&script_path);
+
+        input
+            .into_report()
+            .attach_printable("Cannot open concatenated files")
+            .change_context(FileDecoderError)
+    }
+
     pub fn start(&mut self) -> Result<(), FileDecoderError> {
         let mut demuxer_data: Option<DemuxerData> = None;
         swap(&mut self.demuxer_data, &mut demuxer_data);
 
-        self.threads.push(thread::spawn({
+        self.spawn_worker("ffplay-demuxer", {
             let mut demuxer_data = demuxer_data.unwrap();
             move || -> Result<(), FileDecoderError> {
                 // let mut demuxer_data = demuxer_data.unwrap();
+                if let Some(trim_start) = demuxer_data.trim_start {
+                    let seek_to = (trim_start.as_millis() as i64).rescale_with(
+                        Rational(1, 1000),
+                        TIME_BASE,
+                        Rounding::Zero,
+                    );
+                    debug!("seek to trim start {}", seek_to);
+                    FileDecoder::seek_input(
+                        &mut demuxer_data.stream,
+                        seek_to,
+                        demuxer_data.seek_flags,
+                        KeyframeSearch::Default,
+                    )
+                    .into_report()
+                    .attach_printable(format!("Cannot seek to trim start {}", seek_to))
+                    .change_context(FileDecoderError)?;
+                }
+
+                // (timestamp in ms, packet size in bytes) of the packets in the bitrate window
+                let mut bitrate_window: VecDeque<(i64, usize)> = VecDeque::new();
+                // End of the input reached while looping or after a seek, wait for the
+                // restart or the next seek.
+                let mut end_of_input = false;
+                // Seeks can land past the end, the demuxer stays for the next seek then.
+                let mut seeked = false;
+
                 'demuxing: loop {
                     let rec = demuxer_data.seek_receiver.try_recv();
                     if rec.is_ok() {
-                        let seek_to = rec.ok().unwrap();
+                        let demuxer_seek = rec.ok().unwrap();
+                        end_of_input = false;
+                        seeked = true;
 
                         let rec = demuxer_data.serial_receiver.try_recv();
                         if rec.is_ok() {
                             demuxer_data.seek_serial = rec.ok().unwrap();
                         }
 
-                        let seek_to =
-                            seek_to.rescale_with(demuxer_data.time_base, TIME_BASE, Rounding::Zero);
+                        let seek_to = demuxer_seek.seek_to.rescale_with(
+                            demuxer_data.time_base,
+                            TIME_BASE,
+                            Rounding::Zero,
+                        );
 
                         debug!("seek to {}", seek_to);
                         // demuxer_data
                         //     .stream
                         //     .seek(0, RangeFull)
                         //     .map_err(FileDecoderError::FfmpegError)?;
+                        FileDecoder::seek_input(
+                            &mut demuxer_data.stream,
+                            seek_to,
+                            demuxer_data.seek_flags,
+                            demuxer_seek.keyframe,
+                        )
+                        .into_report()
+                        .attach_printable(format!("Cannot seek to {}", seek_to))
+                        .change_context(FileDecoderError)?;
+                        for (_, packet_queue) in &demuxer_data.packet_queues {
+                            packet_queue.clear();
+                        }
+                        bitrate_window.clear();
                         demuxer_data
-                            .stream
-                            .seek(seek_to, RangeFull)
-                            .into_report()
-                            .attach_printable(format!("Cannot seek to {}", seek_to))
-                            .change_context(FileDecoderError)?;
-                        demuxer_data.packet_queue.clear();
+                            .stats_counters
+                            .measured_bitrate_kbps
+                            .store(0, Ordering::Relaxed);
                     }
 
-                    if let Some((stream, packet)) = demuxer_data.stream.packets().next() {
+                    if end_of_input {
+                        thread::sleep(FileDecoder::POLL_INTERVAL);
+                    } else if let Some((stream, packet)) = demuxer_data.stream.packets().next() {
                         if stream.index() == demuxer_data.stream_index {
+                            if let Some(timestamp) = packet.dts().or_else(|| packet.pts()) {
+                                let timestamp = timestamp.rescale_with(
+                                    demuxer_data.time_base,
+                                    Rational(1, 1000),
+                                    Rounding::Zero,
+                                );
+                                bitrate_window.push_back((timestamp, packet.size()));
+                                while let Some((oldest, _)) = bitrate_window.front() {
+                                    if timestamp - oldest <= FileDecoder::BITRATE_WINDOW_MS {
+                                        break;
+                                    }
+                                    bitrate_window.pop_front();
+                                }
+                                let bytes: usize =
+                                    bitrate_window.iter().map(|(_, size)| size).sum();
+                                let span_ms = match bitrate_window.front() {
+                                    Some((oldest, _)) if timestamp > *oldest => timestamp - oldest,
+                                    _ => FileDecoder::BITRATE_WINDOW_MS,
+                                };
+                                // bytes * 8 / span_ms equals kbit/s
+                                demuxer_data
+                                    .stats_counters
+                                    .measured_bitrate_kbps
+                                    .store((bytes as u64 * 8) / span_ms as u64, Ordering::Relaxed);
+                            }
+                        }
+                        if let Some((_, packet_queue)) = demuxer_data
+                            .packet_queues
+                            .iter()
+                            .find(|(stream_index, _)| *stream_index == stream.index())
+                        {
                             trace!(
-                                "Demuxer: queue packet with pts {}",
-                                packet.pts().unwrap_or_default()
+                                "Demuxer: queue packet with pts {} of stream {}",
+                                packet.pts().unwrap_or_default(),
+                                stream.index()
                             );
                             let packet_data = PacketData::new(demuxer_data.seek_serial, packet);
-                            demuxer_data
-                                .packet_queue
-                                .add(DelayItem::new(Some(packet_data), Instant::now()));
+                            packet_queue.add(DelayItem::new(Some(packet_data), Instant::now()));
                         }
                     } else {
-                        debug!("no more packages, quit demuxer");
-                        demuxer_data
-                            .packet_queue
-                            .add(DelayItem::new(None, Instant::now()));
-                        break 'demuxing;
+                        for (_, packet_queue) in &demuxer_data.packet_queues {
+                            packet_queue.add(DelayItem::new(None, Instant::now()));
+                        }
+                        if !demuxer_data.looping.load(Ordering::Relaxed) && !seeked {
+                            debug!("no more packages, quit demuxer");
+                            break 'demuxing;
+                        }
+                        debug!("no more packages, wait for the loop restart or a seek");
+                        end_of_input = true;
                     }
 
-                    if demuxer_data.running.upgrade().is_none() {
+                    if !demuxer_data.running.load(Ordering::Relaxed) {
                         trace!("quit demuxer, running is false");
                         break 'demuxing;
                     }
@@ -286,166 +2405,1093 @@ impl FileDecoder {
                 debug!("################### return from demuxer spawn");
                 Ok(())
             }
-        }));
+        })?;
 
-        let mut decoder_data: Option<DecoderData> = None;
-        swap(&mut self.decoder_data, &mut decoder_data);
+        // Like an accurate seek the trim start drops the frames before it.
+        let skip_until = self
+            .trim_start
+            .map(|trim_start| trim_start.as_millis() as u64);
+        for mut decoder_data in std::mem::take(&mut self.decoder_data) {
+            decoder_data.skip_until = skip_until;
+            self.spawn_worker("ffplay-decoder", move || FileDecoder::decode(decoder_data))?;
+        }
 
-        self.threads.push(thread::spawn({
-            let mut decoder_data = decoder_data.unwrap();
-            move || -> Result<(), FileDecoderError> {
-                let mut scaler = context::Context::get(
-                    decoder_data.decoder.format(),
-                    decoder_data.decoder.width(),
-                    decoder_data.decoder.height(),
-                    decoder_data.pixel_format,
-                    decoder_data.decoder.width(),
-                    decoder_data.decoder.height(),
-                    Flags::BILINEAR,
-                )
-                .into_report()
-                .attach_printable("Cannot get scaling context")
-                .change_context(FileDecoderError)?;
+        if let Some(mut audio_decoder_data) = self.audio_decoder_data.take() {
+            audio_decoder_data.skip_until = skip_until;
+            self.spawn_worker("ffplay-audio", move || {
+                FileDecoder::decode_audio(audio_decoder_data)
+            })?;
+        }
 
-                let mut sent_eof = false;
-                let mut last_frame_time: Option<u64> = None;
-
-                let mut receive_and_process_decoded_frame =
-                    |current_serial: &u64,
-                     decoder: &mut ffmpeg_rs::decoder::Video,
-                     last_frame_time: &mut Option<u64>,
-                     video_producer_queue: &VideoQueue|
-                     -> Result<bool, FileDecoderError> {
-                        let mut decoded = Video::empty();
-                        let status = decoder.receive_frame(&mut decoded);
-                        match status {
-                            Err(err) => match err {
-                                ffmpeg_rs::Error::Eof => {
-                                    debug!("Decoder returned EOF, send EOF frame");
-                                    decoder_data
-                                        .video_queue
-                                        .add(DelayItem::new(None, Instant::now()));
-                                    Ok(true)
-                                }
-                                ffmpeg_rs::Error::Other {
-                                    errno: ffmpeg_rs::util::error::EAGAIN,
-                                } => Ok(false),
-                                _ => Err(Report::new(FileDecoderError)
-                                    .attach_printable(format!("{err}"))),
-                            },
-                            Ok(()) => {
-                                trace!(
-                                    "decoder: received frame with pts {}",
-                                    decoded.timestamp().unwrap_or_default()
-                                );
-                                let mut rgb_frame = Video::empty();
-                                scaler
-                                    .run(&decoded, &mut rgb_frame)
-                                    .into_report()
-                                    .attach_printable("Scaling failed")
-                                    .change_context(FileDecoderError)?;
-                                rgb_frame.set_pts(decoded.timestamp());
-
-                                let deocded_timestamp = decoded.timestamp().unwrap_or(0);
-                                let frame_time = deocded_timestamp.rescale_with(
-                                    decoder_data.time_base,
-                                    Rational(1, 1000),
-                                    Rounding::Zero,
-                                ) as u64;
+        if let Some(subtitle_decoder_data) = self.subtitle_decoder_data.take() {
+            self.spawn_worker("ffplay-subtitles", move || {
+                FileDecoder::decode_subtitles(subtitle_decoder_data)
+            })?;
+        }
 
-                                let mut frame_diff: u64 = 0;
-                                if let Some(prev_time) = *last_frame_time {
-                                    frame_diff = frame_time - prev_time;
-                                }
+        if self.frame_callback.lock().unwrap().is_some() {
+            self.spawn_delivery()?;
+        }
+
+        Ok(())
+    }
 
-                                *last_frame_time = Some(frame_time);
+    /// Call `callback` with every frame of the main video stream as soon as it is decoded,
+    /// an alternative to taking the frames from [`FileDecoder::video_queue`]. Do not use
+    /// both, the frames go to one of them. Frames of a previous serial, e.g. from before a
+    /// seek, are skipped. The frames are not paced, schedule them by their frame time. While
+    /// paused only the frame a seek lands on is delivered.
+    ///
+    /// The callback runs on a dedicated thread, so it must be `Send`. It must not block for
+    /// long, the decoding stalls once the video queue is full. A new callback replaces the
+    /// previous one, it stays registered after [`FileDecoder::open`].
+    pub fn on_frame(&mut self, callback: FrameCallback) -> Result<(), FileDecoderError> {
+        let registered = self.frame_callback.lock().unwrap().replace(callback);
+        if registered.is_none() && !self.threads.is_empty() {
+            self.spawn_delivery()?;
+        }
+        Ok(())
+    }
 
-                                trace!(
-                                    "decoder: add frame with pts {} to video queue",
-                                    deocded_timestamp
-                                );
-                                video_producer_queue.add(DelayItem::new(
-                                    Some(VideoData::new(
-                                        *current_serial,
-                                        frame_time,
-                                        frame_diff,
-                                        rgb_frame,
-                                    )),
-                                    Instant::now(),
-                                ));
-                                trace!(
-                                    "got back from adding to video queue running={}",
-                                    decoder_data.running.upgrade().is_none()
-                                );
-                                Ok(decoder_data.running.upgrade().is_none())
-                            }
+    fn spawn_delivery(&mut self) -> Result<(), FileDecoderError> {
+        let video_queue = self.video_queue.clone();
+        let current_serial = self.current_serial.clone();
+        let frame_callback = self.frame_callback.clone();
+        let paused = self.paused.clone();
+        let position_ms = self.position_ms.clone();
+        let running = match &self.running {
+            Some(running) => running.clone(),
+            None => return Ok(()),
+        };
+        self.spawn_worker("ffplay-delivery", move || {
+            let mut delivered_serial = None;
+            while running.load(Ordering::Relaxed) {
+                // While paused only the first frame after a seek is delivered.
+                if paused.load(Ordering::Relaxed)
+                    && delivered_serial == Some(current_serial.load(Ordering::Relaxed))
+                {
+                    thread::sleep(FileDecoder::POLL_INTERVAL);
+                    continue;
+                }
+                let video_data = match video_queue.poll(FileDecoder::POLL_INTERVAL) {
+                    Some(video_delay_item) => video_delay_item.data,
+                    None => continue,
+                };
+                // The end of the stream, a seek or loop restart can still bring new frames.
+                let video_data = match video_data {
+                    Some(video_data) => video_data,
+                    None => continue,
+                };
+                if video_data.serial != current_serial.load(Ordering::Relaxed) {
+                    trace!("delivery: drop frame with old serial {}", video_data.serial);
+                    continue;
+                }
+                delivered_serial = Some(video_data.serial);
+                position_ms.store(video_data.frame_time, Ordering::Relaxed);
+                if let Some(callback) = frame_callback.lock().unwrap().as_mut() {
+                    callback(&video_data);
+                }
+            }
+            debug!("################### return from delivery spawn");
+            Ok(())
+        })
+    }
+
+    // Initialize FFmpeg once per process, every FileDecoder calls it.
+    fn init_ffmpeg() -> Result<(), FileDecoderError> {
+        static FFMPEG_INIT: Once = Once::new();
+        let mut result = Ok(());
+        FFMPEG_INIT.call_once(|| result = ffmpeg_rs::init());
+        result
+            .into_report()
+            .attach_printable("FFmpeg init failed")
+            .change_context(FileDecoderError)
+    }
+
+    // Run `worker` in a thread named `name`, an error is passed on to the consumer, see
+    // FileDecoder::try_take_error().
+    fn spawn_worker<F>(&mut self, name: &str, worker: F) -> Result<(), FileDecoderError>
+    where
+        F: FnOnce() -> Result<(), FileDecoderError> + Send + 'static,
+    {
+        let error_sender = self.error_sender.clone();
+        let thread = thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || {
+                worker().or_else(|err| {
+                    warn!("FileDecoder: thread exited with error {:?}", err);
+                    match error_sender {
+                        Some(error_sender) => {
+                            error_sender.send(err).map_err(|mpsc::SendError(err)| err)
                         }
-                    };
+                        None => Err(err),
+                    }
+                })
+            })
+            .into_report()
+            .attach_printable(format!("Cannot spawn thread {}", name))
+            .change_context(FileDecoderError)?;
+        self.threads.push(thread);
+        Ok(())
+    }
 
-                'decoding: loop {
-                    let rec = decoder_data.serial_receiver.try_recv();
-                    if rec.is_ok() {
-                        decoder_data.seek_serial = rec.ok().unwrap();
-                        debug!("decoder: received serial {}", decoder_data.seek_serial);
-                        sent_eof = false;
-                        decoder_data.decoder.flush();
-                        decoder_data.video_queue.clear();
-                        last_frame_time = None;
+    fn decode_audio(mut audio_decoder_data: AudioDecoderData) -> Result<(), FileDecoderError> {
+        let decoder = &audio_decoder_data.decoder;
+        let channel_layout = if decoder.channel_layout().is_empty() {
+            ChannelLayout::default(decoder.channels() as i32)
+        } else {
+            decoder.channel_layout()
+        };
+        let mut resampler = resampling::Context::get(
+            decoder.format(),
+            channel_layout,
+            decoder.rate(),
+            format::Sample::I16(format::sample::Type::Packed),
+            ChannelLayout::STEREO,
+            FileDecoder::AUDIO_SAMPLE_RATE,
+        )
+        .into_report()
+        .attach_printable("Cannot get resampling context")
+        .change_context(FileDecoderError)?;
+
+        let mut sent_eof = false;
+        // Serial of the loop restart or seek received while waiting at the end.
+        let mut restart_serial = None;
+        // Seeks can land past the end, the decoder waits for the next seek there.
+        let mut seeked = false;
+        'decoding: loop {
+            let rec = match restart_serial.take() {
+                Some(decoder_serial) => Ok(decoder_serial),
+                None => audio_decoder_data.serial_receiver.try_recv(),
+            };
+            if let Ok(decoder_serial) = rec {
+                seeked = true;
+                audio_decoder_data.seek_serial = decoder_serial.serial;
+                audio_decoder_data.skip_until = decoder_serial.skip_until;
+                debug!(
+                    "audio decoder: received serial {}",
+                    audio_decoder_data.seek_serial
+                );
+                sent_eof = false;
+                audio_decoder_data.decoder.flush();
+                audio_decoder_data.audio_queue.clear();
+            }
+            if !sent_eof {
+                let packet_data = match FileDecoder::take_packet(
+                    &audio_decoder_data.packet_queue,
+                    &audio_decoder_data.running,
+                ) {
+                    Some(packet_data) => packet_data,
+                    None => break 'decoding,
+                };
+                if let Some(packet_data) = packet_data {
+                    if audio_decoder_data.seek_serial != packet_data.serial {
+                        trace!("audio decoder: serial wrong continue");
+                        continue 'decoding;
                     }
-                    if !sent_eof {
-                        let packet_delay_item = decoder_data.packet_queue.take();
-                        let packet_data = packet_delay_item.data;
-
-                        if let Some(packet_data) = packet_data {
-                            trace!("decoder: got packet");
-                            if decoder_data.seek_serial != packet_data.serial {
-                                trace!("decoder: serial wrong continue");
-                                continue 'decoding;
+                    audio_decoder_data
+                        .decoder
+                        .send_packet(&packet_data.packet)
+                        .into_report()
+                        .change_context(FileDecoderError)?;
+                } else {
+                    debug!("Send EOF to audio decoder");
+                    sent_eof = true;
+                    audio_decoder_data
+                        .decoder
+                        .send_eof()
+                        .into_report()
+                        .change_context(FileDecoderError)?;
+                }
+            }
+
+            loop {
+                let mut decoded = Audio::empty();
+                match audio_decoder_data.decoder.receive_frame(&mut decoded) {
+                    Ok(()) => {
+                        let mut resampled = Audio::empty();
+                        resampler
+                            .run(&decoded, &mut resampled)
+                            .into_report()
+                            .attach_printable("Resampling failed")
+                            .change_context(FileDecoderError)?;
+                        let frame_time = decoded.timestamp().unwrap_or(0).max(0).rescale_with(
+                            audio_decoder_data.time_base,
+                            Rational(1, 1000),
+                            Rounding::Zero,
+                        ) as u64;
+                        if let Some(skip_until) = audio_decoder_data.skip_until {
+                            if frame_time < skip_until {
+                                trace!("audio decoder: drop frame with frame time {}", frame_time);
+                                continue;
                             }
-                            trace!(
-                                "decoder: send packet with pts {}",
-                                packet_data.packet.pts().unwrap_or_default()
-                            );
-                            decoder_data
-                                .decoder
-                                .send_packet(&packet_data.packet)
-                                .into_report()
-                                .change_context(FileDecoderError)?;
+                        }
+                        let len = resampled.samples() * FileDecoder::AUDIO_CHANNELS as usize * 2;
+                        let mut samples: Vec<i16> = resampled.data(0)[..len]
+                            .chunks_exact(2)
+                            .map(|bytes| i16::from_ne_bytes([bytes[0], bytes[1]]))
+                            .collect();
+                        let gain = if audio_decoder_data.muted.load(Ordering::Relaxed) {
+                            0.0
                         } else {
-                            debug!("Send EOF to decoder");
-                            sent_eof = true;
-                            decoder_data
-                                .decoder
-                                .send_eof()
-                                .into_report()
-                                .change_context(FileDecoderError)?;
+                            FileDecoder::volume_gain(
+                                audio_decoder_data.volume_curve,
+                                audio_decoder_data.volume.load(Ordering::Relaxed),
+                            )
+                        };
+                        FileDecoder::apply_volume(&mut samples, gain);
+                        trace!("audio decoder: add frame with frame time {}", frame_time);
+                        audio_decoder_data.audio_queue.add(DelayItem::new(
+                            Some(AudioData {
+                                serial: audio_decoder_data.seek_serial,
+                                frame_time,
+                                samples,
+                            }),
+                            Instant::now(),
+                        ));
+                    }
+                    Err(ffmpeg_rs::Error::Eof) => {
+                        debug!("Audio decoder returned EOF, send EOF frame");
+                        audio_decoder_data
+                            .audio_queue
+                            .add(DelayItem::new(None, Instant::now()));
+                        if !audio_decoder_data.looping.load(Ordering::Relaxed) && !seeked {
+                            break 'decoding;
+                        }
+                        restart_serial = FileDecoder::wait_for_serial(
+                            &audio_decoder_data.serial_receiver,
+                            &audio_decoder_data.running,
+                        );
+                        if restart_serial.is_none() {
+                            break 'decoding;
                         }
+                        continue 'decoding;
+                    }
+                    Err(ffmpeg_rs::Error::Other {
+                        errno: ffmpeg_rs::util::error::EAGAIN,
+                    }) => break,
+                    Err(err) => {
+                        return Err(Report::new(FileDecoderError).attach_printable(format!("{err}")))
                     }
+                }
+            }
 
-                    let is_eof = receive_and_process_decoded_frame(
-                        &decoder_data.seek_serial,
-                        &mut decoder_data.decoder,
-                        &mut last_frame_time,
-                        &decoder_data.video_queue,
-                    )?;
-                    trace!("received frame is_eof={}", is_eof);
-                    if is_eof {
+            if !audio_decoder_data.running.load(Ordering::Relaxed) {
+                trace!("quit audio decoder, running is false");
+                break 'decoding;
+            }
+        }
+        debug!("################### return from audio decoder spawn");
+        Ok(())
+    }
+
+    // Gain (0 to 1) of `volume` (0 to MAX_VOLUME) with `curve`.
+    fn volume_gain(curve: VolumeCurve, volume: u32) -> f64 {
+        let fraction = volume.min(FileDecoder::MAX_VOLUME) as f64 / FileDecoder::MAX_VOLUME as f64;
+        match curve {
+            VolumeCurve::Linear => fraction,
+            VolumeCurve::Logarithmic if volume == 0 => 0.0,
+            VolumeCurve::Logarithmic => {
+                let step_db = FileDecoder::VOLUME_RANGE_DB / (FileDecoder::MAX_VOLUME - 1) as f64;
+                let db = -step_db * (FileDecoder::MAX_VOLUME - volume) as f64;
+                10f64.powf(db / 20.0)
+            }
+        }
+    }
+
+    // Scale `samples` by `gain` (0 to 1).
+    fn apply_volume(samples: &mut [i16], gain: f64) {
+        if gain >= 1.0 {
+            return;
+        }
+        for sample in samples {
+            *sample = (*sample as f64 * gain) as i16;
+        }
+    }
+
+    fn decode_subtitles(
+        mut subtitle_decoder_data: SubtitleDecoderData,
+    ) -> Result<(), FileDecoderError> {
+        // Serial of the loop restart or seek received while waiting at the end.
+        let mut restart_serial = None;
+        // Seeks can land past the end, the decoder waits for the next seek there.
+        let mut seeked = false;
+        'decoding: loop {
+            let rec = match restart_serial.take() {
+                Some(decoder_serial) => Ok(decoder_serial),
+                None => subtitle_decoder_data.serial_receiver.try_recv(),
+            };
+            if let Ok(decoder_serial) = rec {
+                seeked = true;
+                subtitle_decoder_data.seek_serial = decoder_serial.serial;
+                debug!(
+                    "subtitle decoder: received serial {}",
+                    subtitle_decoder_data.seek_serial
+                );
+                subtitle_decoder_data.decoder.flush();
+                subtitle_decoder_data.subtitle_queue.clear();
+            }
+            let packet_data = match FileDecoder::take_packet(
+                &subtitle_decoder_data.packet_queue,
+                &subtitle_decoder_data.running,
+            ) {
+                Some(packet_data) => packet_data,
+                None => break 'decoding,
+            };
+            let packet_data = match packet_data {
+                Some(packet_data) => packet_data,
+                None => {
+                    debug!("subtitle decoder: end of stream");
+                    subtitle_decoder_data
+                        .subtitle_queue
+                        .add(DelayItem::new(None, Instant::now()));
+                    if !subtitle_decoder_data.looping.load(Ordering::Relaxed) && !seeked {
+                        break 'decoding;
+                    }
+                    restart_serial = FileDecoder::wait_for_serial(
+                        &subtitle_decoder_data.serial_receiver,
+                        &subtitle_decoder_data.running,
+                    );
+                    if restart_serial.is_none() {
                         break 'decoding;
                     }
+                    continue 'decoding;
                 }
-                debug!("################### return from decoder spawn");
-                Ok(())
+            };
+            if subtitle_decoder_data.seek_serial != packet_data.serial {
+                trace!("subtitle decoder: serial wrong continue");
+                continue 'decoding;
+            }
+
+            let mut subtitle = Subtitle::new();
+            match subtitle_decoder_data
+                .decoder
+                .decode(&packet_data.packet, &mut subtitle)
+            {
+                Ok(true) => {}
+                Ok(false) => continue 'decoding,
+                Err(err) => {
+                    // A broken subtitle is no reason to stop the playback.
+                    warn!("cannot decode subtitle: {}", err);
+                    continue 'decoding;
+                }
+            }
+            let text = subtitle
+                .rects()
+                .filter_map(|rect| match rect {
+                    subtitle::Rect::Text(text) => Some(text.get().to_owned()),
+                    subtitle::Rect::Ass(ass) => Some(FileDecoder::ass_text(ass.get())),
+                    _ => None,
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            if text.is_empty() {
+                trace!("subtitle decoder: skip subtitle without text");
+                continue 'decoding;
+            }
+
+            let to_ms = |timestamp: i64| {
+                timestamp.max(0).rescale_with(
+                    subtitle_decoder_data.time_base,
+                    Rational(1, 1000),
+                    Rounding::Zero,
+                ) as u64
+            };
+            let packet_time = to_ms(packet_data.packet.pts().unwrap_or(0));
+            let start_time = packet_time + u64::from(subtitle.start());
+            let end_time = if subtitle.end() > subtitle.start() {
+                Some(packet_time + u64::from(subtitle.end()))
+            } else if packet_data.packet.duration() > 0 {
+                Some(packet_time + to_ms(packet_data.packet.duration()))
+            } else {
+                None
+            };
+            trace!(
+                "subtitle decoder: add subtitle from {} to {:?}",
+                start_time,
+                end_time
+            );
+            subtitle_decoder_data.subtitle_queue.add(DelayItem::new(
+                Some(SubtitleData {
+                    serial: subtitle_decoder_data.seek_serial,
+                    start_time,
+                    end_time,
+                    text,
+                }),
+                Instant::now(),
+            ));
+        }
+        debug!("################### return from subtitle decoder spawn");
+        Ok(())
+    }
+
+    // Text of an ASS dialogue event ("ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,
+    // Effect,Text", old FFmpeg versions send a full "Dialogue:" line with 10 fields) without
+    // the override blocks in braces, line breaks become newlines.
+    fn ass_text(event: &str) -> String {
+        let (event, fields) = match event.strip_prefix("Dialogue:") {
+            Some(event) => (event, 10),
+            None => (event, 9),
+        };
+        let text = event.splitn(fields, ',').nth(fields - 1).unwrap_or(event);
+        let mut plain = String::with_capacity(text.len());
+        let mut in_override = false;
+        for c in text.chars() {
+            match c {
+                '{' => in_override = true,
+                '}' if in_override => in_override = false,
+                _ if !in_override => plain.push(c),
+                _ => {}
+            }
+        }
+        plain
+            .replace("\\N", "\n")
+            .replace("\\n", "\n")
+            .replace("\\h", " ")
+            .trim()
+            .to_owned()
+    }
+
+    // FFmpeg filters to apply to the decoded frames, `None` if they are used as they are.
+    // yadif outputs a frame per field (mode 1) and only touches frames flagged as interlaced.
+    // The subtitles come last, so eq does not change their colors.
+    fn filter_description(
+        eq: Option<&EqSettings>,
+        deinterlace: bool,
+        subtitle_filter: Option<&str>,
+    ) -> Option<String> {
+        let mut filters = Vec::new();
+        if deinterlace {
+            filters.push("yadif=mode=send_field:deint=interlaced".to_owned());
+        }
+        if let Some(settings) = eq {
+            filters.push(format!(
+                "eq=brightness={}:contrast={}:saturation={}",
+                settings.brightness, settings.contrast, settings.saturation
+            ));
+        }
+        filters.extend(subtitle_filter.map(str::to_owned));
+        if filters.is_empty() {
+            None
+        } else {
+            Some(filters.join(","))
+        }
+    }
+
+    // subtitles filter drawing the subtitle stream `subtitle_index` (counting subtitle streams
+    // only) of `uri`. The path is escaped for the filter options and then for the graph.
+    fn subtitle_filter(uri: &str, subtitle_index: usize) -> String {
+        let escape = |value: &str, special: &[char]| {
+            value.chars().fold(String::new(), |mut escaped, c| {
+                if special.contains(&c) {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+                escaped
+            })
+        };
+        let filename = escape(
+            &escape(uri, &['\\', '\'', ':']),
+            &['\\', '\'', '[', ']', ',', ';'],
+        );
+        format!("subtitles=filename={}:si={}", filename, subtitle_index)
+    }
+
+    fn decode(mut decoder_data: DecoderData) -> Result<(), FileDecoderError> {
+        let burn_in_timestamps = decoder_data.burn_in_timestamps
+            && if timestamp_overlay::is_supported(decoder_data.pixel_format) {
+                true
+            } else {
+                warn!(
+                    "cannot burn in timestamps into {:?} frames",
+                    decoder_data.pixel_format
+                );
+                false
+            };
+
+        // Without overrides full range frames stay in full range, the scaler would convert
+        // them to limited range by default, e.g. from 10 to 8 bit.
+        let scaler_color_ranges = match (
+            decoder_data.input_color_range,
+            decoder_data.output_color_range,
+        ) {
+            (None, None) if decoder_data.decoder.color_range() == color::Range::JPEG => {
+                (Some(color::Range::JPEG), Some(color::Range::JPEG))
+            }
+            color_ranges => color_ranges,
+        };
+
+        // Frames already in the output format are queued as they are, unless the scaler has to
+        // resize them, change the color range or the frame gets drawn into, which needs an own
+        // copy.
+        let mut scaler = if decoder_data.decoder.format() == decoder_data.pixel_format
+            && decoder_data.output_size.is_none()
+            && decoder_data.input_color_range.is_none()
+            && decoder_data.output_color_range.is_none()
+            && !burn_in_timestamps
+        {
+            debug!("decoder: no conversion of {:?}", decoder_data.pixel_format);
+            None
+        } else {
+            Some(FileDecoder::create_scaler(
+                decoder_data.decoder.format(),
+                decoder_data.decoder.width(),
+                decoder_data.decoder.height(),
+                decoder_data.pixel_format,
+                decoder_data.output_size,
+                decoder_data.scaler_flags,
+                scaler_color_ranges.0,
+                scaler_color_ranges.1,
+            )?)
+        };
+
+        // Filters of the frames, the serial they were built for and whether they contain eq,
+        // see FileDecoder::filter_description().
+        let mut video_filter: Option<(u64, bool, VideoFilter)> = None;
+
+        let mut sent_eof = false;
+        let mut output_state = OutputState::default();
+        // Decoded frames not yet queued, sorted by timestamp.
+        let mut reorder_buffer: Vec<DecodedFrame> = Vec::new();
+
+        let send_end_of_stream = |serial: u64| {
+            if decoder_data.primary && !decoder_data.looping.load(Ordering::Relaxed) {
+                let _ = decoder_data
+                    .event_sender
+                    .send(DecoderEvent::EndOfStream { serial });
+            }
+        };
+        let emit_frame = |current_serial: &u64,
+                          skip_until: &Option<u64>,
+                          output_state: &mut OutputState,
+                          video_producer_queue: &VideoQueue,
+                          decoded_frame: DecodedFrame|
+         -> bool {
+            let frame_time = decoded_frame.timestamp.rescale_with(
+                decoder_data.time_base,
+                Rational(1, 1000),
+                Rounding::Zero,
+            ) as u64;
+
+            if let Some(trim_end) = decoder_data.trim_end {
+                if frame_time > trim_end.as_millis() as u64 {
+                    debug!(
+                        "decoder: frame time {} behind trim end, send EOF frame",
+                        frame_time
+                    );
+                    video_producer_queue.add(DelayItem::new(None, Instant::now()));
+                    send_end_of_stream(*current_serial);
+                    return true;
+                }
+            }
+
+            if let Some(skip_until) = *skip_until {
+                if frame_time < skip_until {
+                    trace!(
+                        "decoder: drop frame with frame time {} before {}",
+                        frame_time,
+                        skip_until
+                    );
+                    output_state.last_skipped = Some(decoded_frame);
+                    return false;
+                }
+            }
+
+            let mut queue_video_data = |frame_time: u64, mut frame: Video| {
+                if output_state.last_frame_time.is_none() && decoder_data.primary {
+                    let mut seek_requested =
+                        decoder_data.stats_counters.seek_requested.lock().unwrap();
+                    if let Some((serial, requested_at)) = *seek_requested {
+                        if serial == *current_serial {
+                            let seek_latency = requested_at.elapsed();
+                            debug!("seek {} took {:?}", serial, seek_latency);
+                            *decoder_data.stats_counters.seek_latency.lock().unwrap() =
+                                Some(seek_latency);
+                            *seek_requested = None;
+                        }
+                    }
+                }
+
+                let mut frame_diff: u64 = 0;
+                if let Some(prev_time) = output_state.last_frame_time {
+                    frame_diff = frame_time.saturating_sub(prev_time);
+                }
+
+                output_state.last_frame_time = Some(frame_time);
+
+                if burn_in_timestamps {
+                    let text = format!(
+                        "{:02}:{:02}:{:02}.{:03} {}",
+                        frame_time / 3_600_000,
+                        frame_time / 60_000 % 60,
+                        frame_time / 1000 % 60,
+                        frame_time % 1000,
+                        decoded_frame.timestamp
+                    );
+                    timestamp_overlay::burn_in(&mut frame, &text);
+                }
+
+                trace!(
+                    "decoder: add frame with pts {} to video queue",
+                    decoded_frame.timestamp
+                );
+                let frame_index = output_state.frame_index;
+                output_state.frame_index += 1;
+                let (frame, hw_frame) = if FileDecoder::is_hw_frame(&frame) {
+                    (Video::empty(), Some(HwFrame(frame)))
+                } else {
+                    (frame, None)
+                };
+                let mut video_data = VideoData::new(
+                    *current_serial,
+                    frame_time,
+                    frame_diff,
+                    decoded_frame.timestamp,
+                    frame_index,
+                    frame,
+                    decoded_frame.color_primaries,
+                    decoded_frame.color_transfer_characteristic,
+                    decoded_frame.color_space,
+                    decoded_frame.color_range,
+                    decoded_frame.keyframe,
+                    std::mem::take(&mut output_state.landing),
+                );
+                video_data.hw_frame = hw_frame;
+                if let Some(frame_cache) = &decoder_data.frame_cache {
+                    frame_cache.push(video_data.clone());
+                }
+                if decoder_data.primary {
+                    decoder_data
+                        .stats_counters
+                        .decoded_frames
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                video_producer_queue.add(DelayItem::new(Some(video_data), Instant::now()));
+            };
+
+            match decoder_data.output_fps {
+                None => queue_video_data(frame_time, decoded_frame.frame),
+                Some(output_fps) => {
+                    // Deliver this frame for every grid point up to its frame time,
+                    // which drops frames for lower and repeats them for higher rates.
+                    let interval = 1000.0 / output_fps;
+                    let mut next_output_time =
+                        output_state.next_output_time.unwrap_or(frame_time as f64);
+                    while next_output_time <= frame_time as f64 {
+                        let frame = if FileDecoder::is_hw_frame(&decoded_frame.frame) {
+                            FileDecoder::ref_frame(&decoded_frame.frame)
+                        } else {
+                            decoded_frame.frame.clone()
+                        };
+                        queue_video_data(next_output_time as u64, frame);
+                        next_output_time += interval;
+                    }
+                    output_state.next_output_time = Some(next_output_time);
+                }
+            }
+            false
+        };
+
+        let mut receive_and_process_decoded_frame = |current_serial: &u64,
+                                                     skip_until: &Option<u64>,
+                                                     decoder: &mut ffmpeg_rs::decoder::Video,
+                                                     output_state: &mut OutputState,
+                                                     reorder_buffer: &mut Vec<DecodedFrame>,
+                                                     video_producer_queue: &VideoQueue|
+         -> Result<bool, FileDecoderError> {
+            let mut decoded = Video::empty();
+            let end_of_stream = match decoder.receive_frame(&mut decoded) {
+                Ok(()) => false,
+                Err(ffmpeg_rs::Error::Eof) => true,
+                Err(ffmpeg_rs::Error::Other {
+                    errno: ffmpeg_rs::util::error::EAGAIN,
+                }) => return Ok(false),
+                Err(err) => {
+                    return Err(Report::new(FileDecoderError).attach_printable(format!("{err}")))
+                }
+            };
+            let frames = if end_of_stream {
+                // Filters like yadif hold frames back until the next one arrives.
+                match video_filter.as_mut() {
+                    Some((_, _, filter)) => filter
+                        .flush()
+                        .into_report()
+                        .attach_printable("Flushing the filter failed")
+                        .change_context(FileDecoderError)?,
+                    None => Vec::new(),
+                }
+            } else if decoder_data.keep_hw_frames && FileDecoder::is_hw_frame(&decoded) {
+                // The filters and the scaler need the frame in system memory.
+                vec![decoded]
+            } else {
+                trace!(
+                    "decoder: received frame with pts {}",
+                    decoded.timestamp().unwrap_or_default()
+                );
+                let decoded = FileDecoder::download_frame(decoded)?;
+                // Without changes eq is left out, the graph is rebuilt when it comes in.
+                let eq = decoder_data
+                    .eq
+                    .as_deref()
+                    .map(SharedEq::load)
+                    .filter(|settings| *settings != EqSettings::default());
+                match FileDecoder::filter_description(
+                    eq.as_ref(),
+                    decoder_data.deinterlace,
+                    decoder_data.subtitle_filter.as_deref(),
+                ) {
+                    Some(filters) => {
+                        let rebuild =
+                            video_filter
+                                .as_ref()
+                                .map_or(true, |(serial, has_eq, filter)| {
+                                    serial != current_serial
+                                        || *has_eq != eq.is_some()
+                                        || !filter.accepts(&decoded)
+                                });
+                        if rebuild {
+                            debug!("decoder: filter with {}", filters);
+                            video_filter = Some((
+                                *current_serial,
+                                eq.is_some(),
+                                VideoFilter::new(
+                                    &filters,
+                                    decoded.format(),
+                                    decoded.width(),
+                                    decoded.height(),
+                                    decoder_data.time_base,
+                                    decoder.aspect_ratio(),
+                                )
+                                .into_report()
+                                .attach_printable(format!("Cannot create filter {}", filters))
+                                .change_context(FileDecoderError)?,
+                            ));
+                        }
+                        let (_, _, filter) = video_filter.as_mut().unwrap();
+                        if let (Some(shared_eq), Some(settings)) = (&decoder_data.eq, eq) {
+                            // A new graph starts with the current values.
+                            if shared_eq.changed.swap(false, Ordering::Acquire) && !rebuild {
+                                for (command, value) in [
+                                    ("brightness", settings.brightness),
+                                    ("contrast", settings.contrast),
+                                    ("saturation", settings.saturation),
+                                ] {
+                                    filter
+                                        .send_command("eq", command, &value.to_string())
+                                        .into_report()
+                                        .attach_printable(format!("Cannot set eq {}", command))
+                                        .change_context(FileDecoderError)?;
+                                }
+                            }
+                        }
+                        filter
+                            .run(&decoded)
+                            .into_report()
+                            .attach_printable("Filtering failed")
+                            .change_context(FileDecoderError)?
+                    }
+                    None => {
+                        video_filter = None;
+                        vec![decoded]
+                    }
+                }
+            };
+            for decoded in frames {
+                let decoded_timestamp = decoded.timestamp().unwrap_or(0);
+                let color_primaries = decoded.color_primaries();
+                let color_transfer_characteristic = decoded.color_transfer_characteristic();
+                let color_space = decoded.color_space();
+                let color_range = decoded.color_range();
+                let keyframe = decoded.is_key() || decoded.kind() == picture::Type::I;
+                let rgb_frame = if FileDecoder::is_hw_frame(&decoded)
+                    || (scaler.is_none() && decoded.format() == decoder_data.pixel_format)
+                {
+                    decoded
+                } else {
+                    if scaler.as_ref().map_or(true, |scaler| {
+                        let input = scaler.input();
+                        input.format != decoded.format()
+                            || input.width != decoded.width()
+                            || input.height != decoded.height()
+                    }) {
+                        // Hardware decoders output e.g. NV12 instead of the format of
+                        // the stream, and streams can change the size midway.
+                        debug!("decoder: scale from {:?}", decoded.format());
+                        scaler = Some(FileDecoder::create_scaler(
+                            decoded.format(),
+                            decoded.width(),
+                            decoded.height(),
+                            decoder_data.pixel_format,
+                            decoder_data.output_size,
+                            decoder_data.scaler_flags,
+                            scaler_color_ranges.0,
+                            scaler_color_ranges.1,
+                        )?);
+                    }
+                    let mut rgb_frame = Video::empty();
+                    if let Some(scaler) = &mut scaler {
+                        scaler
+                            .run(&decoded, &mut rgb_frame)
+                            .into_report()
+                            .attach_printable("Scaling failed")
+                            .change_context(FileDecoderError)?;
+                    }
+                    rgb_frame.set_pts(decoded.timestamp());
+                    rgb_frame
+                };
+
+                if output_state.seeking && decoder_data.primary {
+                    output_state.seeking = false;
+                    let position = decoded_timestamp.max(0).rescale_with(
+                        decoder_data.time_base,
+                        Rational(1, 1000),
+                        Rounding::Zero,
+                    );
+                    let _ = decoder_data
+                        .event_sender
+                        .send(DecoderEvent::SeekKeyframeReached {
+                            serial: *current_serial,
+                            position: Duration::from_millis(position as u64),
+                        });
+                }
+                let last_timestamp = output_state.last_frame_time.map(|last_frame_time| {
+                    (last_frame_time as i64).rescale_with(
+                        Rational(1, 1000),
+                        decoder_data.time_base,
+                        Rounding::Zero,
+                    )
+                });
+                if matches!(last_timestamp, Some(last) if decoded_timestamp < last) {
+                    debug!(
+                        "decoder: drop frame with pts {} arriving too late",
+                        decoded_timestamp
+                    );
+                    continue;
+                }
+
+                let position =
+                    reorder_buffer.partition_point(|frame| frame.timestamp <= decoded_timestamp);
+                reorder_buffer.insert(
+                    position,
+                    DecodedFrame::new(
+                        decoded_timestamp,
+                        rgb_frame,
+                        color_primaries,
+                        color_transfer_characteristic,
+                        color_space,
+                        color_range,
+                        keyframe,
+                    ),
+                );
+
+                // Frames can leave the decoder out of order by at most the
+                // number of frames the codec delays for reordering.
+                let reorder_depth = unsafe { (*decoder.as_ptr()).has_b_frames }.max(0) as usize;
+                while reorder_buffer.len() > reorder_depth {
+                    let decoded_frame = reorder_buffer.remove(0);
+                    if emit_frame(
+                        current_serial,
+                        skip_until,
+                        output_state,
+                        video_producer_queue,
+                        decoded_frame,
+                    ) {
+                        return Ok(true);
+                    }
+                }
+            }
+
+            if end_of_stream {
+                debug!("Decoder returned EOF, flush reorder buffer");
+                for decoded_frame in reorder_buffer.drain(..) {
+                    if emit_frame(
+                        current_serial,
+                        skip_until,
+                        output_state,
+                        video_producer_queue,
+                        decoded_frame,
+                    ) {
+                        return Ok(true);
+                    }
+                }
+                if output_state.landing {
+                    // The seek target is past the last frame. Queue the last frame before it,
+                    // if any, and wait for the next seek instead of ending the playback.
+                    debug!("Decoder returned EOF right after the serial change");
+                    if let Some(decoded_frame) = output_state.last_skipped.take() {
+                        emit_frame(
+                            current_serial,
+                            &None,
+                            output_state,
+                            video_producer_queue,
+                            decoded_frame,
+                        );
+                    }
+                    return Ok(true);
+                }
+                debug!("Decoder returned EOF, send EOF frame");
+                decoder_data
+                    .video_queue
+                    .add(DelayItem::new(None, Instant::now()));
+                send_end_of_stream(*current_serial);
+                return Ok(true);
+            }
+
+            trace!(
+                "got back from adding to video queue running={}",
+                decoder_data.running.load(Ordering::Relaxed)
+            );
+            Ok(!decoder_data.running.load(Ordering::Relaxed))
+        };
+
+        // Serial of the loop restart or seek received while waiting at the end.
+        let mut restart_serial = None;
+        // Seeks can land past the end, the decoder waits for the next seek there.
+        let mut seeked = false;
+        'decoding: loop {
+            let rec = match restart_serial.take() {
+                Some(decoder_serial) => Ok(decoder_serial),
+                None => decoder_data.serial_receiver.try_recv(),
+            };
+            if let Ok(decoder_serial) = rec {
+                seeked = true;
+                decoder_data.seek_serial = decoder_serial.serial;
+                decoder_data.skip_until = decoder_serial.skip_until;
+                debug!("decoder: received serial {}", decoder_data.seek_serial);
+                sent_eof = false;
+                decoder_data.decoder.flush();
+                decoder_data.video_queue.clear();
+                if let Some(frame_cache) = &decoder_data.frame_cache {
+                    frame_cache.clear();
+                }
+                output_state = OutputState {
+                    seeking: true,
+                    landing: true,
+                    ..Default::default()
+                };
+                reorder_buffer.clear();
+            }
+            // A queued frame is enough while paused, e.g. to step to it. Seeks clear the
+            // queue, so the frame to refresh after a seek is still decoded.
+            if decoder_data.paused.load(Ordering::Relaxed) && !decoder_data.video_queue.is_empty() {
+                if !decoder_data.running.load(Ordering::Relaxed) {
+                    break 'decoding;
+                }
+                thread::sleep(FileDecoder::POLL_INTERVAL);
+                continue 'decoding;
             }
-        }));
+            if !sent_eof {
+                let packet_data = match FileDecoder::take_packet(
+                    &decoder_data.packet_queue,
+                    &decoder_data.running,
+                ) {
+                    Some(packet_data) => packet_data,
+                    None => break 'decoding,
+                };
 
+                if let Some(packet_data) = packet_data {
+                    trace!("decoder: got packet");
+                    if decoder_data.seek_serial != packet_data.serial {
+                        trace!("decoder: serial wrong continue");
+                        continue 'decoding;
+                    }
+                    trace!(
+                        "decoder: send packet with pts {}",
+                        packet_data.packet.pts().unwrap_or_default()
+                    );
+                    decoder_data
+                        .decoder
+                        .send_packet(&packet_data.packet)
+                        .into_report()
+                        .change_context(FileDecoderError)?;
+                } else {
+                    debug!("Send EOF to decoder");
+                    sent_eof = true;
+                    decoder_data
+                        .decoder
+                        .send_eof()
+                        .into_report()
+                        .change_context(FileDecoderError)?;
+                }
+            }
+
+            let is_eof = receive_and_process_decoded_frame(
+                &decoder_data.seek_serial,
+                &decoder_data.skip_until,
+                &mut decoder_data.decoder,
+                &mut output_state,
+                &mut reorder_buffer,
+                &decoder_data.video_queue,
+            )?;
+            trace!("received frame is_eof={}", is_eof);
+            if is_eof {
+                if (!decoder_data.looping.load(Ordering::Relaxed) && !seeked)
+                    || !decoder_data.running.load(Ordering::Relaxed)
+                {
+                    break 'decoding;
+                }
+                restart_serial = FileDecoder::wait_for_serial(
+                    &decoder_data.serial_receiver,
+                    &decoder_data.running,
+                );
+                if restart_serial.is_none() {
+                    break 'decoding;
+                }
+            }
+        }
+        debug!("################### return from decoder spawn");
         Ok(())
     }
 
+    /// Take the next packet (`None` at the end of the input) from `packet_queue`. Returns
+    /// `None` if the decoder is stopped while waiting for it.
+    fn take_packet(packet_queue: &PacketQueue, running: &AtomicBool) -> Option<Option<PacketData>> {
+        loop {
+            if let Some(packet_delay_item) = packet_queue.poll(FileDecoder::POLL_INTERVAL) {
+                return Some(packet_delay_item.data);
+            }
+            if !running.load(Ordering::Relaxed) {
+                trace!("decoder: stopped while waiting for a packet");
+                return None;
+            }
+        }
+    }
+
+    /// Block until a decoder thread waiting at the end receives the serial of the loop
+    /// restart (see [`FileDecoder::restart_loop`]) or of a seek. `None` once the decoder is
+    /// stopped.
+    fn wait_for_serial(
+        serial_receiver: &mpsc::Receiver<DecoderSerial>,
+        running: &AtomicBool,
+    ) -> Option<DecoderSerial> {
+        debug!("decoder: end of stream, wait for the loop restart or a seek");
+        loop {
+            match serial_receiver.recv_timeout(FileDecoder::POLL_INTERVAL) {
+                Ok(decoder_serial) => return Some(decoder_serial),
+                Err(mpsc::RecvTimeoutError::Timeout) if running.load(Ordering::Relaxed) => {}
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Stop the threads and clear the queues. Calling it again, e.g. from `drop()` after an
+    /// explicit stop, does nothing.
     pub fn stop(&mut self) {
+        if self.running.is_none() && self.threads.is_empty() {
+            trace!("FileDecoder::stop(): already stopped");
+            return;
+        }
         debug!("FileDecoder::stop()");
-        self.running.take();
-        self.packet_queue.clear();
-        self.video_queue.clear();
+        if let Some(running) = self.running.take() {
+            running.store(false, Ordering::Relaxed);
+        }
+        // Threads blocked on a full queue only see the stop once there is space again, so keep
+        // the queues empty until all threads have finished. Waiting threads poll the flag.
+        self.clear_queues();
+        while self.threads.iter().any(|thread| !thread.is_finished()) {
+            thread::sleep(FileDecoder::POLL_INTERVAL);
+            self.clear_queues();
+        }
         while let Some(t) = self.threads.pop() {
             match t.join() {
                 Ok(res) => match res {
@@ -461,43 +3507,688 @@ impl FileDecoder {
         }
     }
 
+    fn clear_queues(&self) {
+        self.packet_queue.clear();
+        self.video_queue.clear();
+        self.audio_packet_queue.clear();
+        self.audio_queue.clear();
+        self.subtitle_packet_queue.clear();
+        self.subtitle_queue.clear();
+        for extra_stream in &self.extra_streams {
+            extra_stream.packet_queue.clear();
+            extra_stream.video_queue.clear();
+        }
+    }
+
+    /// Switch to the input `uri`: stops the threads, initializes against the new input and
+    /// starts again. Returns the serial of the frames of the new input. Size and pixel format
+    /// can differ from the previous input, so the consumer has to check them and recreate its
+    /// texture if needed. If the new input cannot be opened, the decoder stays stopped. While
+    /// paused it stays paused and the first frame of the new input needs a refresh, see
+    /// [`FileDecoder::needs_refresh`].
+    pub fn open(&mut self, uri: String) -> Result<u64, FileDecoderError> {
+        self.stop();
+
+        self.uri = uri;
+        self.concat_files.clear();
+        self.seek_serial += 1;
+        self.current_serial
+            .store(self.seek_serial, Ordering::Relaxed);
+        self.refresh_serial = None;
+        self.presented_serial = None;
+        self.presented_frame_index = None;
+        self.presented_frame_time = None;
+        self.audio_clock.reset();
+        if let Some(frame_cache) = &self.frame_cache {
+            frame_cache.clear();
+        }
+        self.stats_counters = Arc::new(StatsCounters::default());
+        self.repeated_frames = 0;
+        self.dropped_frames = 0;
+        self.displayed_frames = 0;
+
+        self.init()?;
+        self.start()?;
+        if self.is_paused() {
+            self.refresh_serial = Some(self.seek_serial);
+        }
+        Ok(self.seek_serial)
+    }
+
+    /// Width of the frames in the video queue, see [`FileDecoderBuilder::output_size`].
     pub fn width(&self) -> u32 {
-        self.width
+        self.output_size.map_or(self.width, |(width, _)| width)
     }
 
+    /// Height of the frames in the video queue, see [`FileDecoderBuilder::output_size`].
     pub fn height(&self) -> u32 {
-        self.height
+        self.output_size.map_or(self.height, |(_, height)| height)
+    }
+
+    /// Time base of the main video stream, the unit of [`VideoData::pts`].
+    pub fn time_base(&self) -> Rational {
+        self.time_base
+    }
+
+    /// Width to height ratio of the pixels, not 1:1 for anamorphic video.
+    pub fn sample_aspect_ratio(&self) -> Rational {
+        self.sample_aspect_ratio
+    }
+
+    /// Width to height ratio to display the frames with, i.e. the native frame size stretched
+    /// by the [`FileDecoder::sample_aspect_ratio`]. An output size is stretched to it as well.
+    pub fn display_aspect_ratio(&self) -> Rational {
+        Rational(
+            (self.width as i64 * self.sample_aspect_ratio.numerator() as i64) as i32,
+            (self.height as i64 * self.sample_aspect_ratio.denominator() as i64).max(1) as i32,
+        )
+        .reduce()
     }
 
-    pub fn seek(&mut self, seek_to: i64) -> Result<u64, FileDecoderError> {
+    /// Clockwise rotation in degrees (0, 90, 180 or 270) to display the frames upright. The
+    /// frames are not rotated, [`FileDecoder::width`] and [`FileDecoder::height`] are the
+    /// unrotated size.
+    pub fn rotation(&self) -> u32 {
+        self.rotation
+    }
+
+    /// Capacity of the packet queues, see [`FileDecoderBuilder::packet_queue_capacity`].
+    pub fn packet_queue_capacity(&self) -> usize {
+        self.packet_queue_capacity
+    }
+
+    /// Capacity of the video queues, see [`FileDecoderBuilder::frame_queue_capacity`].
+    pub fn frame_queue_capacity(&self) -> usize {
+        self.frame_queue_capacity
+    }
+
+    /// Seek to `position` of the media. The target is clamped to the start and, if known, the
+    /// duration of the media. Returns the serial of the seek, the frames with it start at the
+    /// target. While paused it stays paused and shows the target like
+    /// [`FileDecoder::seek_and_pause`].
+    pub fn seek_to(&mut self, position: Duration) -> Result<u64, FileDecoderError> {
+        let seek_to = (position.as_millis() as i64).rescale_with(
+            Rational(1, 1000),
+            self.time_base,
+            Rounding::Zero,
+        );
+        let (serial, _) = if self.is_paused() {
+            self.seek_and_pause(seek_to)
+        } else {
+            self.seek(seek_to)
+        }?;
+        Ok(serial)
+    }
+
+    /// Seek to `seek_to` in the time base of the video stream, the low-level variant of
+    /// [`FileDecoder::seek_to`]. The target is clamped to the start and, if known, the
+    /// duration of the media. Returns the serial of the seek and the clamped target. The
+    /// frames start at the keyframe before the target, unless
+    /// [`FileDecoderBuilder::accurate_seek`] is set.
+    pub fn seek(&mut self, seek_to: i64) -> Result<(u64, i64), FileDecoderError> {
+        let seek_to = self.clamp_seek_target(seek_to);
+        let skip_until = self.accurate_seek.then(|| {
+            seek_to.rescale_with(self.time_base, Rational(1, 1000), Rounding::Zero) as u64
+        });
+        let serial = self.request_seek(seek_to, skip_until, KeyframeSearch::Default)?;
+        Ok((serial, seek_to))
+    }
+
+    /// Seek to the fraction `fraction` (clamped to 0.0 to 1.0) of the duration, e.g. for a
+    /// progress bar. Fails if the duration is not known. Returns the serial of the seek and
+    /// the target like [`FileDecoder::seek`]. While paused it stays paused and shows the
+    /// target like [`FileDecoder::seek_and_pause`].
+    pub fn seek_fraction(&mut self, fraction: f64) -> Result<(u64, i64), FileDecoderError> {
+        let duration = self.duration.ok_or_else(|| {
+            Report::new(FileDecoderError)
+                .attach_printable("Cannot seek to a fraction of an unknown duration")
+        })?;
+        let target_ms = self.start_time_ms as i64
+            + (duration.as_millis() as f64 * fraction.clamp(0.0, 1.0)) as i64;
+        debug!("seek to {:.3} of the duration ({} ms)", fraction, target_ms);
+        let seek_to = target_ms.rescale_with(Rational(1, 1000), self.time_base, Rounding::Zero);
+        if self.is_paused() {
+            self.seek_and_pause(seek_to)
+        } else {
+            self.seek(seek_to)
+        }
+    }
+
+    fn clamp_seek_target(&self, seek_to: i64) -> i64 {
+        let seek_to = seek_to.max(0);
+        match self.duration {
+            Some(duration) => seek_to.min(
+                (self.start_time_ms as i64 + duration.as_millis() as i64).rescale_with(
+                    Rational(1, 1000),
+                    self.time_base,
+                    Rounding::Zero,
+                ),
+            ),
+            None => seek_to,
+        }
+    }
+
+    /// Snap playback to `time` of an externally provided timeline. Seeks there and lets the
+    /// decoder drop all frames before `time`, so the first frame with the returned serial is
+    /// the one matching `time`. The consumer is expected to restart its presentation clock
+    /// when it sees the new serial.
+    pub fn resync_to(&mut self, time: Duration) -> Result<u64, FileDecoderError> {
+        let time_ms = time.as_millis() as i64;
+        let seek_to = time_ms.rescale_with(Rational(1, 1000), self.time_base, Rounding::Zero);
+        self.request_seek(seek_to, Some(time_ms as u64), KeyframeSearch::Default)
+    }
+
+    /// Drop all queued packets and frames and decode again from the last presented frame,
+    /// e.g. to get frames reflecting changed settings. The decoder can only restart at a
+    /// keyframe, so this seeks back to the last presented position and skips the frames
+    /// before it. Returns the serial of the fresh frames.
+    pub fn flush(&mut self) -> Result<u64, FileDecoderError> {
+        let position = self.presented_frame_time.unwrap_or(self.start_time_ms);
+        debug!("flush at {} ms", position);
+        self.resync_to(Duration::from_millis(position))
+    }
+
+    /// Seek to `seek_to` and stay paused there. The consumer keeps pulling frames while
+    /// [`FileDecoder::needs_refresh`] is true, presents the first one with the returned
+    /// serial and reports it via [`FileDecoder::frame_presented`]. The target is clamped like
+    /// in [`FileDecoder::seek`].
+    pub fn seek_and_pause(&mut self, seek_to: i64) -> Result<(u64, i64), FileDecoderError> {
+        let (serial, seek_to) = self.seek(seek_to)?;
+        self.paused.store(true, Ordering::Relaxed);
+        self.refresh_serial = Some(serial);
+        Ok((serial, seek_to))
+    }
+
+    /// Seek to the next keyframe after the last presented frame and stay paused there, see
+    /// [`FileDecoder::seek_and_pause`]. The position of the keyframe is reported with
+    /// [`DecoderEvent::SeekCompleted`].
+    pub fn seek_to_next_keyframe(&mut self) -> Result<u64, FileDecoderError> {
+        let position = self.presented_frame_time.unwrap_or(self.start_time_ms) + 1;
+        self.seek_to_keyframe(position, KeyframeSearch::AtOrAfter)
+    }
+
+    /// Seek to the keyframe before the last presented frame and stay paused there, see
+    /// [`FileDecoder::seek_to_next_keyframe`].
+    pub fn seek_to_previous_keyframe(&mut self) -> Result<u64, FileDecoderError> {
+        let position = self
+            .presented_frame_time
+            .unwrap_or(self.start_time_ms)
+            .saturating_sub(1);
+        self.seek_to_keyframe(position, KeyframeSearch::AtOrBefore)
+    }
+
+    fn seek_to_keyframe(
+        &mut self,
+        position_ms: u64,
+        keyframe: KeyframeSearch,
+    ) -> Result<u64, FileDecoderError> {
+        let seek_to =
+            (position_ms as i64).rescale_with(Rational(1, 1000), self.time_base, Rounding::Zero);
+        debug!("seek to {:?} keyframe of {} ms", keyframe, position_ms);
+        let serial = self.request_seek(seek_to, None, keyframe)?;
+        self.paused.store(true, Ordering::Relaxed);
+        self.refresh_serial = Some(serial);
+        Ok(serial)
+    }
+
+    /// Step back to the frame before the last presented one and stay paused. The frame comes
+    /// from the frame cache if it holds it (see [`FileDecoderBuilder::frame_cache`]), the
+    /// consumer presents it and reports it via [`FileDecoder::frame_presented`] as usual.
+    /// Otherwise it seeks to the frame like [`FileDecoder::seek_and_pause`]. The video queue
+    /// continues after the newest decoded frame, so resuming playback after stepping back
+    /// through the cache should resync via [`FileDecoder::resync_to`]. Returns `None` if no
+    /// frame was presented yet.
+    pub fn step_back(&mut self) -> Result<Option<BackwardStep>, FileDecoderError> {
+        let (serial, frame_time) = match (self.presented_serial, self.presented_frame_time) {
+            (Some(serial), Some(frame_time)) => (serial, frame_time),
+            _ => return Ok(None),
+        };
+        self.paused.store(true, Ordering::Relaxed);
+        if let Some(video_data) = self
+            .frame_cache
+            .as_ref()
+            .and_then(|frame_cache| frame_cache.frame_before(serial, frame_time))
+        {
+            trace!("step back to cached frame {}", video_data.frame_time);
+            return Ok(Some(BackwardStep::Cached(video_data)));
+        }
+
+        let frame_duration = if self.average_frame_rate.numerator() > 0 {
+            (1000.0 / f64::from(self.average_frame_rate)).round() as u64
+        } else {
+            1
+        };
+        let target = frame_time.saturating_sub(frame_duration.max(1));
+        debug!("step back to {} ms by seeking", target);
+        let serial = self.resync_to(Duration::from_millis(target))?;
+        self.refresh_serial = Some(serial);
+        Ok(Some(BackwardStep::Seeking(serial)))
+    }
+
+    /// Pause or resume. While paused the video decoders stop once a frame is queued, so the
+    /// demuxer and the audio decoder block on their full queues as well. The consumer
+    /// restarts its presentation clock on resume, the stream time stood still meanwhile.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+        if !paused {
+            self.refresh_serial = None;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Start over instead of ending at the end of the input. The threads wait at the end until
+    /// the consumer reaches the EOF item of the video queue and calls
+    /// [`FileDecoder::restart_loop`]. Off by default.
+    pub fn set_loop(&mut self, enabled: bool) {
+        self.looping.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.looping.load(Ordering::Relaxed)
+    }
+
+    /// Seek back to the start after the consumer got the EOF item of the video queue. Returns
+    /// the serial of the frames from the start, or `None` if not looping and playback ends.
+    /// The consumer restarts its presentation clock like after any other seek.
+    pub fn restart_loop(&mut self) -> Result<Option<u64>, FileDecoderError> {
+        if !self.is_looping() {
+            return Ok(None);
+        }
+        debug!("end reached, restart at {} ms", self.start_time_ms);
+        let serial = self.resync_to(Duration::from_millis(self.start_time_ms))?;
+        Ok(Some(serial))
+    }
+
+    /// Set the playback speed `factor`, e.g. 0.5 for half and 2.0 for double speed, clamped
+    /// to [`FileDecoder::MIN_SPEED`] and [`FileDecoder::MAX_SPEED`]. The consumer paces the
+    /// frames and the audio accordingly, see [`AudioData::samples_at_speed`].
+    pub fn set_speed(&mut self, factor: f64) {
+        self.speed = if factor.is_nan() {
+            1.0
+        } else {
+            factor.clamp(FileDecoder::MIN_SPEED, FileDecoder::MAX_SPEED)
+        };
+        debug!("playback speed {}", self.speed);
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// True while paused, but the target frame of a [`FileDecoder::seek_and_pause`] still
+    /// needs to be presented.
+    pub fn needs_refresh(&self) -> bool {
+        self.is_paused() && self.refresh_serial.is_some()
+    }
+
+    /// Report that `video_data` was presented by the consumer.
+    pub fn frame_presented(&mut self, video_data: &VideoData) {
+        self.displayed_frames += 1;
+        if self.refresh_serial == Some(video_data.serial) {
+            self.refresh_serial = None;
+        }
+        if let Some((serial, target)) = self.pending_seek {
+            if serial == video_data.serial {
+                self.pending_seek = None;
+                self.send_event(DecoderEvent::SeekCompleted {
+                    serial,
+                    target,
+                    position: Duration::from_millis(video_data.frame_time),
+                });
+            }
+        }
+
+        let index_from_time = |frame_rate: Rational| {
+            let frame_time = video_data.frame_time.saturating_sub(self.start_time_ms);
+            ((frame_time as f64 * f64::from(frame_rate)) / 1000.0).round() as u64
+        };
+        self.presented_frame_index = Some(match self.constant_frame_rate {
+            Some(frame_rate) => index_from_time(frame_rate),
+            None => match self.presented_frame_index {
+                Some(index) if self.presented_serial == Some(video_data.serial) => index + 1,
+                _ => index_from_time(self.average_frame_rate),
+            },
+        });
+        self.presented_serial = Some(video_data.serial);
+        self.presented_frame_time = Some(video_data.frame_time);
+        self.position_ms
+            .store(video_data.frame_time, Ordering::Relaxed);
+    }
+
+    /// Playback position, the frame time of the last frame reported via
+    /// [`FileDecoder::frame_presented`] or delivered to the [`FileDecoder::on_frame`]
+    /// callback. It is the presentation position, not the decoding one, which runs ahead by
+    /// the frames in the video queue. On the same timeline as [`FileDecoder::seek_to`] and,
+    /// like it, starts at the start time of the stream, which is not always 0. Before the
+    /// first frame it is the start time.
+    pub fn position(&self) -> Duration {
+        Duration::from_millis(self.position_ms.load(Ordering::Relaxed))
+    }
+
+    /// Report that the consumer repeated the current frame, because the video queue ran
+    /// empty during playback. Counts towards [`Stats::repeated_frames`].
+    pub fn frame_repeated(&mut self) {
+        self.repeated_frames += 1;
+    }
+
+    /// Report that the consumer skipped a frame, because it was too late to be presented.
+    /// Counts towards [`Stats::dropped_frames`].
+    pub fn frame_dropped(&mut self) {
+        self.dropped_frames += 1;
+    }
+
+    /// 0-based index of the last frame reported via [`FileDecoder::frame_presented`].
+    ///
+    /// For constant frame rate streams the index is computed from the frame time and the
+    /// frame rate. For variable frame rate streams the presented frames are counted, after a
+    /// seek the count restarts from an estimate based on the average frame rate, so it is only
+    /// exact when playing from the start.
+    pub fn current_frame_index(&self) -> Option<u64> {
+        self.presented_frame_index
+    }
+
+    fn request_seek(
+        &mut self,
+        seek_to: i64,
+        skip_until: Option<u64>,
+        keyframe: KeyframeSearch,
+    ) -> Result<u64, FileDecoderError> {
         self.seek_serial += 1;
+        self.current_serial
+            .store(self.seek_serial, Ordering::Relaxed);
+        *self.stats_counters.seek_requested.lock().unwrap() =
+            Some((self.seek_serial, Instant::now()));
+        let target = Duration::from_millis(seek_to.max(0).rescale_with(
+            self.time_base,
+            Rational(1, 1000),
+            Rounding::Zero,
+        ) as u64);
+        self.pending_seek = Some((self.seek_serial, target));
+        self.send_event(DecoderEvent::SeekRequested {
+            serial: self.seek_serial,
+            target,
+        });
         self.demuxer_serial_sender
             .as_ref()
             .unwrap()
             .send(self.seek_serial)
             .into_report()
             .change_context(FileDecoderError)?;
-        self.decoder_serial_sender
-            .as_ref()
-            .unwrap()
-            .send(self.seek_serial)
-            .into_report()
-            .change_context(FileDecoderError)?;
+        for decoder_serial_sender in &self.decoder_serial_senders {
+            decoder_serial_sender
+                .send(DecoderSerial::new(self.seek_serial, skip_until))
+                .into_report()
+                .change_context(FileDecoderError)?;
+        }
         self.demuxer_seek_sender
             .as_ref()
             .unwrap()
-            .send(seek_to)
+            .send(DemuxerSeek::new(seek_to, keyframe))
             .into_report()
             .change_context(FileDecoderError)?;
         Ok(self.seek_serial)
     }
 
+    /// Error of a decoding thread, `None` if there is none. The thread has ended then, so
+    /// playback does not continue properly, unlike at the end of the input.
+    pub fn try_take_error(&self) -> Option<Report<FileDecoderError>> {
+        self.error_receiver
+            .as_ref()
+            .and_then(|error_receiver| error_receiver.try_recv().ok())
+    }
+
+    /// Next pending event, `None` if there is none.
+    pub fn try_recv_event(&self) -> Option<DecoderEvent> {
+        self.event_receiver
+            .as_ref()
+            .and_then(|event_receiver| event_receiver.try_recv().ok())
+    }
+
+    fn send_event(&self, event: DecoderEvent) {
+        if let Some(event_sender) = &self.event_sender {
+            // The receiver is owned by self, so sending cannot fail.
+            let _ = event_sender.send(event);
+        }
+    }
+
     pub fn video_queue(&self) -> VideoQueue {
         self.video_queue.clone()
     }
 
+    /// Queue of the `n`-th stream set via [`FileDecoderBuilder::extra_video_streams`]. Its
+    /// frames carry the same serials as the ones of [`FileDecoder::video_queue`].
+    pub fn extra_video_queue(&self, n: usize) -> Option<VideoQueue> {
+        self.extra_streams
+            .get(n)
+            .map(|extra_stream| extra_stream.video_queue.clone())
+    }
+
+    /// Width and height of the `n`-th extra video stream.
+    pub fn extra_video_size(&self, n: usize) -> Option<(u32, u32)> {
+        self.extra_streams
+            .get(n)
+            .map(|extra_stream| (extra_stream.width, extra_stream.height))
+    }
+
+    /// Stop decoding the audio, e.g. when no audio device can be opened: nothing would drain
+    /// the audio queue and the demuxer stalls once it is full, which stalls the video as
+    /// well. Initializes the decoder again without audio, so call it before
+    /// [`FileDecoder::start`]. Inputs opened later with [`FileDecoder::open`] stay without
+    /// audio.
+    pub fn disable_audio(&mut self) -> Result<(), FileDecoderError> {
+        self.disable_audio = true;
+        if !self.has_audio {
+            return Ok(());
+        }
+        if !self.threads.is_empty() {
+            return Err(Report::new(FileDecoderError)
+                .attach_printable("Cannot disable the audio of a started decoder"));
+        }
+        debug!("disable audio");
+        self.init()
+    }
+
+    /// True if the audio is decoded, see [`FileDecoder::audio_queue`].
+    pub fn has_audio(&self) -> bool {
+        self.has_audio
+    }
+
+    /// Queue of the decoded audio, `None` if the input has no audio stream.
+    pub fn audio_queue(&self) -> Option<AudioQueue> {
+        if self.has_audio {
+            Some(self.audio_queue.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Adjust brightness, contrast and saturation of the main video stream, values out of
+    /// range are clamped. Applies from the next decoded frame on, the frames in the video
+    /// queue keep the previous settings. The defaults leave the frames untouched.
+    pub fn set_eq(&mut self, settings: EqSettings) {
+        self.eq.store(settings.clamped());
+    }
+
+    pub fn eq(&self) -> EqSettings {
+        self.eq.load()
+    }
+
+    /// Set the audio volume between 0 and [`FileDecoder::MAX_VOLUME`] (the default, the audio
+    /// as it is), higher values are clamped. Applies to the audio decoded from now on, the
+    /// audio in the audio queue keeps the previous volume.
+    pub fn set_volume(&mut self, volume: u32) {
+        self.volume
+            .store(volume.min(FileDecoder::MAX_VOLUME), Ordering::Relaxed);
+    }
+
+    pub fn volume(&self) -> u32 {
+        self.volume.load(Ordering::Relaxed)
+    }
+
+    /// Gain of the [`FileDecoder::volume`] in dB, e.g. to show it next to the volume.
+    /// Negative infinity at volume 0. Does not account for [`FileDecoder::set_muted`].
+    pub fn volume_db(&self) -> f64 {
+        20.0 * FileDecoder::volume_gain(self.volume_curve, self.volume()).log10()
+    }
+
+    /// Silence the audio without changing the [`FileDecoder::volume`], which applies again
+    /// after unmuting.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    /// Queue of the decoded subtitles, `None` without subtitles, see
+    /// [`FileDecoderBuilder::subtitles`]. The queue holds only a few subtitles, so the consumer
+    /// takes them as they come and keeps them until their end time.
+    pub fn subtitle_queue(&self) -> Option<SubtitleQueue> {
+        if self.has_subtitles {
+            Some(self.subtitle_queue.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Clock of the audio playback, the consumer of the [`AudioQueue`] keeps it updated.
+    pub fn audio_clock(&self) -> Arc<AudioClock> {
+        self.audio_clock.clone()
+    }
+
+    /// Clock the playback is synchronized to, [`SyncMode::AudioMaster`] falls back to
+    /// [`SyncMode::ExternalClock`] without audio.
+    pub fn sync_mode(&self) -> SyncMode {
+        match self.sync_mode {
+            Some(SyncMode::AudioMaster) | None if self.has_audio => SyncMode::AudioMaster,
+            Some(SyncMode::VideoMaster) => SyncMode::VideoMaster,
+            _ => SyncMode::ExternalClock,
+        }
+    }
+
+    /// Time (in ms) of the master clock to schedule the video against, which is the audio
+    /// clock with [`SyncMode::AudioMaster`]. `None` while no audio plays and with the other
+    /// sync modes, the consumer paces the video by the wall clock then.
+    pub fn master_clock_ms(&self) -> Option<u64> {
+        match self.sync_mode() {
+            SyncMode::AudioMaster => self.audio_clock.time_ms(),
+            SyncMode::VideoMaster | SyncMode::ExternalClock => None,
+        }
+    }
+
     pub fn pixel_format(&self) -> Pixel {
-        self.pixel_format
+        self.output_pixel_format
+    }
+
+    /// Color range of the video stream as the decoder reports it. The frames keep it, unless
+    /// they are converted with [`FileDecoderBuilder::color_range_override`].
+    pub fn color_range(&self) -> color::Range {
+        self.color_range
+    }
+
+    /// Color space (matrix) of the video stream as the decoder reports it.
+    pub fn color_space(&self) -> color::Space {
+        self.color_space
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            declared_bitrate_kbps: self.declared_bitrate_kbps,
+            measured_bitrate_kbps: self
+                .stats_counters
+                .measured_bitrate_kbps
+                .load(Ordering::Relaxed),
+            open_latency: self.open_latency,
+            seek_latency: *self.stats_counters.seek_latency.lock().unwrap(),
+            repeated_frames: self.repeated_frames,
+            dropped_frames: self.dropped_frames,
+            decoded_frames: self.stats_counters.decoded_frames.load(Ordering::Relaxed),
+            displayed_frames: self.displayed_frames,
+            queue_depth: self.video_queue.size(),
+        }
+    }
+
+    /// Time of the first frame, the media ends at the start time plus the
+    /// [`FileDecoder::duration`]. Zero for most files, but e.g. MPEG-TS streams start later.
+    pub fn start_time(&self) -> Duration {
+        Duration::from_millis(self.start_time_ms)
+    }
+
+    /// Duration of the media, `None` if it is not known.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    pub fn duration_source(&self) -> DurationSource {
+        self.duration_source
+    }
+
+    /// True for live HLS/DASH streams, which have no duration and cannot be seeked reliably.
+    pub fn is_live(&self) -> bool {
+        self.is_live
+    }
+
+    /// Encoded image (usually JPEG or PNG) of the embedded cover art, if the file has one.
+    pub fn cover_art(&self) -> Option<&[u8]> {
+        self.cover_art.as_deref()
+    }
+
+    /// Codec, size, pixel format, frame rate and bitrate of the main video stream, e.g. for a
+    /// heads-up display. `None` before [`FileDecoder::init`].
+    pub fn media_info(&self) -> Option<&MediaInfo> {
+        self.media_info.as_ref()
+    }
+
+    /// Parameters of the audio from the stream to the device, e.g. to show the conversions.
+    /// `None` without audio and before [`FileDecoder::init`].
+    pub fn audio_spec(&self) -> Option<AudioSpec> {
+        self.audio_source_params.map(|source| AudioSpec {
+            source,
+            output: AudioParams {
+                sample_rate: FileDecoder::AUDIO_SAMPLE_RATE,
+                sample_format: format::Sample::I16(format::sample::Type::Packed),
+                channels: FileDecoder::AUDIO_CHANNELS,
+            },
+            device: self.audio_device_params,
+        })
+    }
+
+    /// Report the parameters the audio device was opened with, for
+    /// [`FileDecoder::audio_spec`].
+    pub fn set_audio_device_params(&mut self, params: AudioParams) {
+        self.audio_device_params = Some(params);
+    }
+
+    /// Start and title of the chapters of the input, e.g. of MKV or MP4 files, sorted by
+    /// start. The start is on the timeline of [`FileDecoder::seek_to`], the title is empty if
+    /// the chapter has none. Empty if the input has no chapters.
+    pub fn chapters(&self) -> Vec<(Duration, String)> {
+        self.chapters.clone()
+    }
+
+    /// Start of the first chapter after `position`, `None` if there is none. Chapters which
+    /// start within two seconds after it are skipped, a seek to a chapter can land on the
+    /// keyframe before its start.
+    pub fn next_chapter(&self, position: Duration) -> Option<Duration> {
+        self.chapters
+            .iter()
+            .map(|(start, _)| *start)
+            .find(|start| *start > position + FileDecoder::CHAPTER_TOLERANCE)
+    }
+
+    /// Start of the chapter at `position`, `None` before the first one. Within the first two
+    /// seconds of a chapter it is the start of the previous one, so repeated calls step back
+    /// chapter by chapter like with most players.
+    pub fn previous_chapter(&self, position: Duration) -> Option<Duration> {
+        self.chapters
+            .iter()
+            .map(|(start, _)| *start)
+            .rev()
+            .find(|start| *start + FileDecoder::CHAPTER_TOLERANCE < position)
     }
 }
 
@@ -506,3 +4197,566 @@ impl Drop for FileDecoder {
         self.stop();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_decoder() -> FileDecoder {
+        FileDecoderBuilder::new("test.mp4".to_owned()).build_uninitialized()
+    }
+
+    #[test]
+    fn byte_offset() {
+        // 1 Mbit/s is 125000 bytes per second.
+        assert_eq!(
+            FileDecoder::byte_offset(2_000_000, 0, 1_000_000),
+            Some(250_000)
+        );
+        assert_eq!(
+            FileDecoder::byte_offset(2_000_000, 1_000_000, 1_000_000),
+            Some(125_000)
+        );
+        assert_eq!(
+            FileDecoder::byte_offset(2_000_000, ffmpeg_rs::ffi::AV_NOPTS_VALUE, 1_000_000),
+            Some(250_000)
+        );
+        assert_eq!(FileDecoder::byte_offset(0, 1_000_000, 1_000_000), Some(0));
+        assert_eq!(FileDecoder::byte_offset(2_000_000, 0, 0), None);
+    }
+
+    #[test]
+    fn output_fps_ignores_invalid_rates() {
+        let mut builder = FileDecoderBuilder::new("test.mp4".to_owned());
+        for fps in [0.0, -25.0, f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+            builder.output_fps(Some(fps));
+            assert_eq!(builder.output_fps, None, "fps {}", fps);
+        }
+        builder.output_fps(Some(29.97));
+        assert_eq!(builder.output_fps, Some(29.97));
+    }
+
+    #[test]
+    fn apply_volume() {
+        let mut samples = vec![1000, -1000, i16::MAX, i16::MIN];
+        FileDecoder::apply_volume(&mut samples, 1.0);
+        assert_eq!(samples, vec![1000, -1000, i16::MAX, i16::MIN]);
+        FileDecoder::apply_volume(&mut samples, 0.5);
+        assert_eq!(samples, vec![500, -500, 16383, -16384]);
+        FileDecoder::apply_volume(&mut samples, 0.0);
+        assert_eq!(samples, vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn default_sync_mode() {
+        let mut file_decoder = file_decoder();
+        assert_eq!(file_decoder.sync_mode(), SyncMode::ExternalClock);
+        file_decoder.has_audio = true;
+        assert_eq!(file_decoder.sync_mode(), SyncMode::AudioMaster);
+        file_decoder.sync_mode = Some(SyncMode::VideoMaster);
+        assert_eq!(file_decoder.sync_mode(), SyncMode::VideoMaster);
+        file_decoder.has_audio = false;
+        file_decoder.sync_mode = Some(SyncMode::AudioMaster);
+        assert_eq!(file_decoder.sync_mode(), SyncMode::ExternalClock);
+    }
+
+    #[test]
+    fn ref_frame_shares_data() {
+        let frame = Video::new(Pixel::YUV420P, 16, 16);
+        assert!(!FileDecoder::is_hw_frame(&frame));
+        let new_ref = FileDecoder::ref_frame(&frame);
+        assert_eq!(new_ref.data(0).as_ptr(), frame.data(0).as_ptr());
+        assert_eq!((new_ref.width(), new_ref.height()), (16, 16));
+    }
+
+    #[test]
+    fn volume_gain() {
+        let max = FileDecoder::MAX_VOLUME;
+        assert_eq!(FileDecoder::volume_gain(VolumeCurve::Linear, max), 1.0);
+        assert_eq!(FileDecoder::volume_gain(VolumeCurve::Linear, max / 2), 0.5);
+        assert_eq!(FileDecoder::volume_gain(VolumeCurve::Linear, 0), 0.0);
+        assert_eq!(FileDecoder::volume_gain(VolumeCurve::Logarithmic, max), 1.0);
+        assert_eq!(FileDecoder::volume_gain(VolumeCurve::Logarithmic, 0), 0.0);
+        let lowest = FileDecoder::volume_gain(VolumeCurve::Logarithmic, 1);
+        assert!((20.0 * lowest.log10() + FileDecoder::VOLUME_RANGE_DB).abs() < 1e-9);
+        // Every step is the same number of dB.
+        let ratio = |volume| {
+            FileDecoder::volume_gain(VolumeCurve::Logarithmic, volume + 1)
+                / FileDecoder::volume_gain(VolumeCurve::Logarithmic, volume)
+        };
+        assert!((ratio(10) - ratio(80)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn filter_description() {
+        assert_eq!(FileDecoder::filter_description(None, false, None), None);
+        assert_eq!(
+            FileDecoder::filter_description(
+                Some(&EqSettings {
+                    brightness: 0.1,
+                    contrast: 1.5,
+                    saturation: 1.0
+                }),
+                true,
+                None
+            ),
+            Some(
+                "yadif=mode=send_field:deint=interlaced,eq=brightness=0.1:contrast=1.5:saturation=1"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            FileDecoder::filter_description(None, false, Some("subtitles=filename=a.mkv:si=0")),
+            Some("subtitles=filename=a.mkv:si=0".to_owned())
+        );
+    }
+
+    #[test]
+    fn subtitle_filter_escapes_path() {
+        assert_eq!(
+            FileDecoder::subtitle_filter("/videos/movie.mkv", 1),
+            "subtitles=filename=/videos/movie.mkv:si=1"
+        );
+        assert_eq!(
+            FileDecoder::subtitle_filter("C:\\it's [1],2.mkv", 0),
+            "subtitles=filename=C\\\\:\\\\\\\\it\\\\\\'s \\[1\\]\\,2.mkv:si=0"
+        );
+    }
+
+    #[test]
+    fn stop_twice() {
+        let mut file_decoder = file_decoder();
+        let running = Arc::new(AtomicBool::new(true));
+        file_decoder.running = Some(running.clone());
+        file_decoder.threads.push(thread::spawn(|| Ok(())));
+
+        file_decoder.stop();
+        assert!(!running.load(Ordering::Relaxed));
+        assert!(file_decoder.running.is_none());
+        assert!(file_decoder.threads.is_empty());
+
+        file_decoder.stop();
+        assert!(file_decoder.running.is_none());
+        assert!(file_decoder.threads.is_empty());
+    }
+
+    #[test]
+    fn seek_past_end() {
+        let mut file_decoder = FileDecoderBuilder::new(crate::tiny_clip())
+            .disable_audio(true)
+            .accurate_seek(true)
+            .build()
+            .unwrap();
+        file_decoder.start().unwrap();
+        let duration = file_decoder.duration().unwrap();
+        let serial = file_decoder
+            .seek_to(duration + Duration::from_secs(10))
+            .unwrap();
+
+        let video_queue = file_decoder.video_queue();
+        let last_frame = loop {
+            let video_data = video_queue
+                .poll(Duration::from_secs(5))
+                .expect("no frame after the seek")
+                .data
+                .expect("end of stream after the seek");
+            if video_data.serial == serial {
+                break video_data;
+            }
+        };
+        assert!(last_frame.is_seek_landing);
+        // The last of the 10 frames.
+        assert_eq!(last_frame.frame_time, 360);
+        // The decoder waits for the next seek instead of ending the stream.
+        assert!(video_queue.poll(Duration::from_millis(200)).is_none());
+        assert!(file_decoder.try_take_error().is_none());
+        file_decoder.stop();
+    }
+
+    #[test]
+    fn disable_audio_before_start() {
+        let mut file_decoder = FileDecoderBuilder::new(crate::testdata("av.avi"))
+            .packet_queue_capacity(1)
+            .build()
+            .unwrap();
+        assert!(file_decoder.has_audio());
+        file_decoder.disable_audio().unwrap();
+        assert!(!file_decoder.has_audio());
+        assert!(file_decoder.audio_queue().is_none());
+        // With the 80 audio packets decoded, but not taken, the demuxer would stall before
+        // the end of the video.
+        assert_eq!(crate::null_output::drain(&mut file_decoder).unwrap(), 10);
+    }
+
+    #[test]
+    fn frames_in_presentation_order() {
+        // The MPEG-1 clip decodes its B-frames after the following P-frame.
+        for (clip, frame_count) in [(crate::tiny_clip(), 10), (crate::testdata("ibp.m1v"), 20)] {
+            let mut file_decoder = FileDecoderBuilder::new(clip.clone())
+                .disable_audio(true)
+                .build()
+                .unwrap();
+            file_decoder.start().unwrap();
+            let video_queue = file_decoder.video_queue();
+            let mut last: Option<(i64, u64)> = None;
+            let mut frames = 0;
+            while let Some(video_data) = video_queue
+                .poll(Duration::from_secs(5))
+                .expect("no end of stream")
+                .data
+            {
+                if let Some((pts, frame_time)) = last {
+                    assert!(video_data.pts > pts, "pts {} in {}", video_data.pts, clip);
+                    assert!(video_data.frame_time > frame_time, "frame time in {}", clip);
+                }
+                last = Some((video_data.pts, video_data.frame_time));
+                frames += 1;
+            }
+            assert_eq!(frames, frame_count, "frames in {}", clip);
+            file_decoder.stop();
+        }
+    }
+
+    #[test]
+    fn scaler_follows_size_change() {
+        // The clip switches from 16x16 to 32x16 after 5 frames.
+        let mut file_decoder = FileDecoderBuilder::new(crate::testdata("resize.m1v"))
+            .pixel_format(Pixel::RGB24)
+            .disable_audio(true)
+            .build()
+            .unwrap();
+        file_decoder.start().unwrap();
+        let video_queue = file_decoder.video_queue();
+        let mut sizes = Vec::new();
+        while let Some(video_data) = video_queue
+            .poll(Duration::from_secs(5))
+            .expect("no end of stream")
+            .data
+        {
+            let frame = &video_data.video_frame;
+            assert_eq!(frame.format(), Pixel::RGB24);
+            sizes.push((frame.width(), frame.height()));
+        }
+        assert!(file_decoder.try_take_error().is_none());
+        assert_eq!(sizes, [vec![(16, 16); 5], vec![(32, 16); 5]].concat());
+        file_decoder.stop();
+    }
+
+    #[test]
+    fn estimate_duration() {
+        let file_decoder = FileDecoderBuilder::new(crate::tiny_clip())
+            .disable_audio(true)
+            .estimate_duration(true)
+            .build()
+            .unwrap();
+        assert_eq!(file_decoder.duration_source(), DurationSource::Estimated);
+        // 10 frames of 40ms.
+        assert_eq!(file_decoder.duration(), Some(Duration::from_millis(400)));
+    }
+
+    #[test]
+    fn trim() {
+        let mut file_decoder = FileDecoderBuilder::new(crate::tiny_clip())
+            .disable_audio(true)
+            .trim(Duration::from_millis(120), Duration::from_millis(240))
+            .build()
+            .unwrap();
+        file_decoder.start().unwrap();
+        let video_queue = file_decoder.video_queue();
+        let mut frame_times = Vec::new();
+        while let Some(video_data) = video_queue
+            .poll(Duration::from_secs(5))
+            .expect("no end of stream")
+            .data
+        {
+            frame_times.push(video_data.frame_time);
+        }
+        assert_eq!(frame_times, vec![120, 160, 200, 240]);
+        file_decoder.stop();
+    }
+
+    #[test]
+    fn trim_swaps_inverted_range() {
+        let mut builder = FileDecoderBuilder::new("test.mp4".to_owned());
+        builder.trim(Duration::from_secs(2), Duration::from_secs(1));
+        assert_eq!(builder.trim_start, Some(Duration::from_secs(1)));
+        assert_eq!(builder.trim_end, Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn keyframes_only() {
+        let count_frames = |keyframes_only| {
+            let mut file_decoder = FileDecoderBuilder::new(crate::testdata("ibp.m1v"))
+                .disable_audio(true)
+                .keyframes_only(keyframes_only)
+                .build()
+                .unwrap();
+            crate::null_output::drain(&mut file_decoder).unwrap()
+        };
+        // Two GOPs IBBPBBPBBP.
+        assert_eq!(count_frames(false), 20);
+        assert_eq!(count_frames(true), 2);
+    }
+
+    #[test]
+    fn concurrent_decoders() {
+        let handles = [(); 2].map(|_| {
+            thread::spawn(|| {
+                let mut file_decoder = FileDecoderBuilder::new(crate::tiny_clip())
+                    .disable_audio(true)
+                    .build()
+                    .unwrap();
+                file_decoder.start().unwrap();
+                let video_queue = file_decoder.video_queue();
+                let mut frames = 0;
+                while let Some(video_data) = video_queue
+                    .poll(Duration::from_secs(5))
+                    .expect("no frame within 5s")
+                    .data
+                {
+                    assert_eq!(video_data.video_frame.width(), 16);
+                    frames += 1;
+                }
+                assert!(file_decoder.try_take_error().is_none());
+                frames
+            })
+        });
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 10);
+        }
+    }
+
+    // Wait up to 5s until `done` holds.
+    fn wait_until(done: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() && Instant::now() < deadline {
+            thread::sleep(FileDecoder::POLL_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn on_frame_delivers_all_frames() {
+        let mut file_decoder = FileDecoderBuilder::new(crate::tiny_clip())
+            .disable_audio(true)
+            .build()
+            .unwrap();
+        let delivered = Arc::new(AtomicU64::new(0));
+        let counter = delivered.clone();
+        file_decoder
+            .on_frame(Box::new(move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }))
+            .unwrap();
+        file_decoder.start().unwrap();
+        wait_until(|| delivered.load(Ordering::Relaxed) >= 10);
+        // Nothing after the end of the stream.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(delivered.load(Ordering::Relaxed), 10);
+        file_decoder.stop();
+    }
+
+    #[test]
+    fn on_frame_skips_old_serials() {
+        let mut file_decoder = file_decoder();
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let frames = delivered.clone();
+        file_decoder
+            .on_frame(Box::new(move |video_data: &VideoData| {
+                frames
+                    .lock()
+                    .unwrap()
+                    .push((video_data.serial, video_data.frame_time));
+            }))
+            .unwrap();
+        // Frames queued before the seek with serial 2.
+        file_decoder.current_serial.store(2, Ordering::Relaxed);
+        for (serial, frame_time) in [(1, 0), (1, 40), (2, 1000), (2, 1040)] {
+            let video_data = VideoData::new(
+                serial,
+                frame_time,
+                40,
+                frame_time as i64,
+                0,
+                Video::empty(),
+                color::Primaries::Unspecified,
+                color::TransferCharacteristic::Unspecified,
+                color::Space::Unspecified,
+                color::Range::Unspecified,
+                true,
+                false,
+            );
+            file_decoder
+                .video_queue
+                .add(DelayItem::new(Some(video_data), Instant::now()));
+        }
+        file_decoder.running = Some(Arc::new(AtomicBool::new(true)));
+        file_decoder.spawn_delivery().unwrap();
+        wait_until(|| delivered.lock().unwrap().len() >= 2);
+        file_decoder.stop();
+        assert_eq!(*delivered.lock().unwrap(), vec![(2, 1000), (2, 1040)]);
+    }
+
+    #[test]
+    fn gray8_output() {
+        let mut file_decoder = FileDecoderBuilder::new(crate::tiny_clip())
+            .pixel_format(Pixel::GRAY8)
+            .disable_audio(true)
+            .build()
+            .unwrap();
+        file_decoder.start().unwrap();
+        let video_data = file_decoder
+            .video_queue()
+            .poll(Duration::from_secs(5))
+            .expect("no frame")
+            .data
+            .expect("end of stream");
+        assert_eq!(video_data.video_frame.format(), Pixel::GRAY8);
+        assert_eq!(video_data.video_frame.planes(), 1);
+        file_decoder.stop();
+    }
+
+    #[test]
+    fn position_follows_presented_frames() {
+        let mut file_decoder = FileDecoderBuilder::new(crate::tiny_clip())
+            .disable_audio(true)
+            .build()
+            .unwrap();
+        assert_eq!(file_decoder.position(), Duration::ZERO);
+        file_decoder.start().unwrap();
+        let video_queue = file_decoder.video_queue();
+        for _ in 0..3 {
+            let video_data = video_queue
+                .poll(Duration::from_secs(5))
+                .expect("no frame")
+                .data
+                .expect("end of stream");
+            file_decoder.frame_presented(&video_data);
+        }
+        // The third frame at 25 fps.
+        assert_eq!(file_decoder.position(), Duration::from_millis(80));
+        file_decoder.stop();
+    }
+
+    #[test]
+    fn end_of_stream_event() {
+        for looping in [false, true] {
+            let mut file_decoder = FileDecoderBuilder::new(crate::tiny_clip())
+                .disable_audio(true)
+                .build()
+                .unwrap();
+            file_decoder.set_loop(looping);
+            file_decoder.start().unwrap();
+            let video_queue = file_decoder.video_queue();
+            while video_queue
+                .poll(Duration::from_secs(5))
+                .expect("no EOF item")
+                .data
+                .is_some()
+            {}
+            // The event is sent right after the EOF item.
+            thread::sleep(Duration::from_millis(100));
+            let event = file_decoder.try_recv_event();
+            if looping {
+                assert_eq!(event, None);
+            } else {
+                assert!(matches!(event, Some(DecoderEvent::EndOfStream { .. })));
+            }
+            file_decoder.stop();
+        }
+    }
+
+    #[test]
+    fn chapter_navigation() {
+        let mut file_decoder = file_decoder();
+        assert_eq!(file_decoder.next_chapter(Duration::ZERO), None);
+        file_decoder.chapters = vec![
+            (Duration::ZERO, "Intro".to_owned()),
+            (Duration::from_secs(60), "Main".to_owned()),
+            (Duration::from_secs(120), String::new()),
+        ];
+        let secs = Duration::from_secs;
+        assert_eq!(file_decoder.next_chapter(secs(10)), Some(secs(60)));
+        // Landed on the keyframe before the chapter.
+        assert_eq!(file_decoder.next_chapter(secs(59)), Some(secs(120)));
+        assert_eq!(file_decoder.next_chapter(secs(130)), None);
+        assert_eq!(file_decoder.previous_chapter(secs(90)), Some(secs(60)));
+        assert_eq!(file_decoder.previous_chapter(secs(61)), Some(secs(0)));
+        assert_eq!(file_decoder.previous_chapter(secs(1)), None);
+    }
+
+    #[test]
+    fn media_info_of_tiny_clip() {
+        let file_decoder = FileDecoderBuilder::new(crate::tiny_clip())
+            .disable_audio(true)
+            .build()
+            .unwrap();
+        let media_info = file_decoder.media_info().unwrap();
+        assert_eq!(media_info.codec_name, "rawvideo");
+        assert_eq!((media_info.width, media_info.height), (16, 16));
+        assert_eq!(media_info.pixel_format, Pixel::YUV420P);
+        assert_eq!(media_info.frame_rate, Rational(25, 1));
+    }
+
+    #[test]
+    fn audio_spec_of_av_clip() {
+        let mut file_decoder = FileDecoderBuilder::new(crate::testdata("av.avi"))
+            .build()
+            .unwrap();
+        let pcm = format::Sample::I16(format::sample::Type::Packed);
+        let audio_spec = file_decoder.audio_spec().unwrap();
+        assert_eq!(
+            audio_spec.source,
+            AudioParams {
+                sample_rate: 8000,
+                sample_format: pcm,
+                channels: 1
+            }
+        );
+        assert_eq!(
+            audio_spec.output.sample_rate,
+            FileDecoder::AUDIO_SAMPLE_RATE
+        );
+        assert_eq!(audio_spec.device, None);
+
+        let device = AudioParams {
+            sample_rate: 44100,
+            sample_format: pcm,
+            channels: 2,
+        };
+        file_decoder.set_audio_device_params(device);
+        assert_eq!(file_decoder.audio_spec().unwrap().device, Some(device));
+        file_decoder.disable_audio().unwrap();
+        assert_eq!(file_decoder.audio_spec(), None);
+    }
+
+    #[test]
+    fn ass_text() {
+        assert_eq!(
+            FileDecoder::ass_text("0,0,Default,,0,0,0,,{\\i1}Hello,{\\i0} world\\Nagain"),
+            "Hello, world\nagain"
+        );
+        assert_eq!(
+            FileDecoder::ass_text("Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,Old\\hformat"),
+            "Old format"
+        );
+    }
+
+    #[test]
+    fn stats_snapshot() {
+        let mut file_decoder = file_decoder();
+        file_decoder
+            .stats_counters
+            .decoded_frames
+            .fetch_add(5, Ordering::Relaxed);
+        file_decoder.frame_dropped();
+        file_decoder.frame_dropped();
+        file_decoder.frame_repeated();
+
+        let stats = file_decoder.stats();
+        assert_eq!(stats.decoded_frames, 5);
+        assert_eq!(stats.dropped_frames, 2);
+        assert_eq!(stats.repeated_frames, 1);
+        assert_eq!(stats.displayed_frames, 0);
+        assert_eq!(stats.queue_depth, 0);
+    }
+}
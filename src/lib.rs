@@ -0,0 +1,31 @@
+//! Decoder of the ffplay example, usable without the SDL player in `main.rs`.
+//!
+//! Build a [`FileDecoder`] with the [`FileDecoderBuilder`], start it and take the decoded
+//! frames as [`VideoData`] from its [`VideoQueue`] or get them with
+//! [`FileDecoder::on_frame`]. A [`playlist::Playlist`] plays several inputs one after the
+//! other, [`marks::Marks`] call back when playback crosses given timestamps.
+
+#[macro_use]
+extern crate derive_new;
+
+pub mod clock;
+pub mod file_decoder;
+pub mod marks;
+pub mod null_output;
+pub mod playlist;
+mod timestamp_overlay;
+mod video_filter;
+
+pub use file_decoder::{FileDecoder, FileDecoderBuilder, FrameCallback, VideoData, VideoQueue};
+
+/// Path of `name` in the `testdata` directory.
+#[cfg(test)]
+pub(crate) fn testdata(name: &str) -> String {
+    format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// 10 frames of 16x16 YUV 4:2:0 at 25 fps.
+#[cfg(test)]
+pub(crate) fn tiny_clip() -> String {
+    testdata("tiny.y4m")
+}
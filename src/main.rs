@@ -1,29 +1,47 @@
 extern crate sdl2;
 
-#[macro_use]
-extern crate derive_new;
-
-mod file_decoder;
+mod pixel_format;
+mod screenshot;
 
+use clap::Parser;
 use error_stack::{Context, IntoReport, Result, ResultExt};
-use ffmpeg_rs::format::{self, Pixel};
-use log::{debug, info, trace};
+use ffmpeg_rs::format::{sample, Pixel, Sample};
+use log::{debug, info, trace, warn};
 use partial_min_max::{max, min};
 use sdl2::{
+    audio::{AudioFormat, AudioSpecDesired},
     event::{Event, WindowEvent},
     keyboard::Keycode,
-    pixels::{Color, PixelFormatEnum},
-    render::TextureValueError,
+    mouse::MouseButton,
+    pixels::Color,
+    render::{BlendMode, Texture, TextureCreator, TextureValueError},
     render::{UpdateTextureError, UpdateTextureYUVError, WindowCanvas},
-    video::WindowBuildError,
-    EventPump, IntegerOrSdlError,
+    ttf::Font,
+    video::{FullscreenType, WindowBuildError, WindowContext},
+    AudioSubsystem, EventPump, IntegerOrSdlError,
 };
 use std::{
-    env, fmt, thread,
+    fmt,
+    path::{Path, PathBuf},
+    thread,
     time::{Duration, Instant},
 };
 
-use crate::file_decoder::VideoData;
+use ffplay::{
+    clock::{audio_is_late, schedule_against, FrameAction, PresentationClock, SyncMode},
+    file_decoder::{
+        AudioParams, AudioQueue, DecoderEvent, EqSettings, MediaInfo, NoVideoStream, SubtitleData,
+        VolumeCurve,
+    },
+    marks::Marks,
+    null_output,
+    playlist::Playlist,
+    FileDecoder, FileDecoderBuilder, VideoData,
+};
+
+use crate::pixel_format::{
+    av_to_sdl_pixel_format_mapper, copy_nv12, displayable_pixel_format, supported_pixel_formats,
+};
 
 #[derive(Debug)]
 enum SDL2Error {
@@ -36,6 +54,11 @@ enum SDL2Error {
     TextureUpdate(UpdateTextureError),
     TextureUpdateYUV(UpdateTextureYUVError),
     TextureValue(TextureValueError),
+    Draw(String),
+    WindowTitle(String),
+    Fullscreen(String),
+    TextureLock(String),
+    Font(String),
 }
 
 impl fmt::Display for SDL2Error {
@@ -66,6 +89,17 @@ impl fmt::Display for SDL2Error {
             SDL2Error::TextureValue(tex_err) => {
                 fmt.write_fmt(format_args!("SDL2 texture value error: {}", tex_err))
             }
+            SDL2Error::Draw(err) => fmt.write_fmt(format_args!("SDL2 draw error: {}", err)),
+            SDL2Error::WindowTitle(err) => {
+                fmt.write_fmt(format_args!("SDL2 window title error: {}", err))
+            }
+            SDL2Error::Fullscreen(err) => {
+                fmt.write_fmt(format_args!("SDL2 fullscreen error: {}", err))
+            }
+            SDL2Error::TextureLock(err) => {
+                fmt.write_fmt(format_args!("SDL2 texture lock error: {}", err))
+            }
+            SDL2Error::Font(err) => fmt.write_fmt(format_args!("SDL2 font error: {}", err)),
         }
     }
 }
@@ -83,18 +117,222 @@ impl fmt::Display for FFplayError {
 
 impl Context for FFplayError {}
 
+/// Play a video file or stream.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// File, URL or directory to play. Several inputs and the files of a directory are
+    /// played one after the other, 'n' skips to the next one.
+    #[arg(short, long, required = true, num_args = 1..)]
+    input: Vec<String>,
+    /// Pixel format to decode to, e.g. yuv420p, gray or rgba (blended with its alpha). Formats
+    /// which cannot be displayed are replaced by a similar one. Without it displayable
+    /// formats of the input are kept and others are converted to yuv420p.
+    #[arg(long, value_parser = parse_pixel_format)]
+    pixel_format: Option<Pixel>,
+    /// Render the window in points scaled up by the system instead of with the full pixel
+    /// resolution on HiDPI displays.
+    #[arg(long)]
+    no_highdpi: bool,
+    /// Opacity of the video from 0 (invisible) to 1, only used with the rgba pixel format.
+    #[arg(long, value_parser = parse_opacity, default_value_t = 1.0)]
+    opacity: f64,
+    /// Scale the video to this size in the decoder instead of uploading it in its native
+    /// size, e.g. 1920x1080.
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_size)]
+    output_size: Option<(u32, u32)>,
+    /// Deinterlace interlaced video, every field becomes a frame.
+    #[arg(long)]
+    deinterlace: bool,
+    /// Start playback at this position.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    start_at: Option<Duration>,
+    /// Seek this far with the arrow keys, cycled through 5, 10, 20 and 60 seconds with 'k'.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "20")]
+    seek_step: Duration,
+    /// Seek exactly to the target instead of the keyframe before it.
+    #[arg(long)]
+    accurate_seek: bool,
+    /// Fail if a network input does not respond within this time.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    timeout: Option<Duration>,
+    /// Start over at the end instead of quitting.
+    #[arg(long = "loop")]
+    looping: bool,
+    /// Log when playback crosses this position, can be given several times.
+    #[arg(long = "mark", value_name = "SECONDS", value_parser = parse_seconds)]
+    marks: Vec<Duration>,
+    /// Log the marks jumped over by a forward seek as well.
+    #[arg(long)]
+    marks_on_seek: bool,
+    /// Play the video without audio.
+    #[arg(long)]
+    no_audio: bool,
+    /// Audio output device, by name or by index. The system default without it or if there
+    /// is no such device.
+    #[arg(long, value_name = "NAME|INDEX")]
+    audio_device: Option<String>,
+    /// Change the volume linearly instead of by the same number of dB per step.
+    #[arg(long)]
+    linear_volume: bool,
+    /// Decode the video as fast as possible without a window, report the frame rate and
+    /// exit.
+    #[arg(long)]
+    benchmark: bool,
+    /// Show the subtitles of the best subtitle stream, toggled with 't'. Only text
+    /// subtitles are supported.
+    #[arg(long)]
+    subtitles: bool,
+    /// Show the subtitles of the stream with this index instead of the best one.
+    #[arg(long, value_name = "INDEX")]
+    subtitle_stream: Option<usize>,
+    /// Draw the subtitles into the video frames, so screenshots contain them. Needs FFmpeg
+    /// with libass.
+    #[arg(long)]
+    burn_in_subtitles: bool,
+    /// Show color bars until the first frame is presented, opening the input can take a
+    /// while.
+    #[arg(long)]
+    test_pattern: bool,
+    /// How strongly the frame timing is pulled towards the stream clock, from 0 (never) to 1
+    /// (with every frame). Only used while no audio plays.
+    #[arg(
+        long,
+        value_name = "GAIN",
+        value_parser = parse_clock_gain,
+        default_value_t = PresentationClock::DEFAULT_GAIN
+    )]
+    clock_gain: f64,
+    /// Clock to synchronize to: audio, video or ext (the system clock). By default the audio,
+    /// the system clock without audio.
+    #[arg(long, value_name = "TYPE", value_parser = parse_sync_mode)]
+    sync: Option<SyncMode>,
+    /// TrueType font of the time overlay and the subtitles.
+    #[arg(long, default_value = DEFAULT_FONT)]
+    font: PathBuf,
+}
+
+const DEFAULT_FONT: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+
+fn parse_pixel_format(name: &str) -> std::result::Result<Pixel, String> {
+    name.parse::<Pixel>().map_err(|err| err.to_string())
+}
+
+fn parse_size(size: &str) -> std::result::Result<(u32, u32), String> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| format!("{} is no WIDTHxHEIGHT size", size))?;
+    let parse = |value: &str| value.parse::<u32>().map_err(|err| err.to_string());
+    Ok((parse(width)?, parse(height)?))
+}
+
+fn parse_seconds(seconds: &str) -> std::result::Result<Duration, String> {
+    let seconds = seconds.parse::<f64>().map_err(|err| err.to_string())?;
+    Duration::try_from_secs_f64(seconds).map_err(|err| err.to_string())
+}
+
+fn parse_clock_gain(gain: &str) -> std::result::Result<f64, String> {
+    let gain = gain.parse::<f64>().map_err(|err| err.to_string())?;
+    if !(0.0..=1.0).contains(&gain) {
+        return Err(format!("clock gain {} is not between 0 and 1", gain));
+    }
+    Ok(gain)
+}
+
+fn parse_opacity(opacity: &str) -> std::result::Result<f64, String> {
+    let opacity = opacity.parse::<f64>().map_err(|err| err.to_string())?;
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(format!("opacity {} is not between 0 and 1", opacity));
+    }
+    Ok(opacity)
+}
+
+fn parse_sync_mode(name: &str) -> std::result::Result<SyncMode, String> {
+    match name {
+        "audio" => Ok(SyncMode::AudioMaster),
+        "video" => Ok(SyncMode::VideoMaster),
+        "ext" => Ok(SyncMode::ExternalClock),
+        _ => Err(format!("{} is not audio, video or ext", name)),
+    }
+}
+
 enum EventState {
     Quit,
     Pause,
     SeekForward,
     SeekBackward,
+    // Seek to the next or previous chapter, by the seek step without chapters.
+    NextChapter,
+    PreviousChapter,
+    CycleSeekStep,
+    StepForward,
+    SpeedDown,
+    SpeedUp,
+    NextKeyframe,
+    PreviousKeyframe,
     Resize,
+    ToggleFullscreen,
+    Screenshot,
+    ToggleInfo,
+    ToggleMediaInfo,
+    ToggleSubtitles,
+    // Change brightness, contrast and saturation by the given steps.
+    AdjustEq(f64, f64, f64),
+    ResetEq,
+    // Change the volume by the given step.
+    AdjustVolume(i32),
+    ToggleMute,
+    PrintStats,
+    // Click at the window position (in points), seeks if it hits the progress bar.
+    Click(i32, i32),
+    Minimized,
+    Restored,
+    FocusLost,
+    FocusGained,
+    TogglePauseOnFocusLoss,
+    NextInput,
+}
+
+// Names of the audio output devices, the index of a name is the index of the device.
+fn audio_output_devices(audio_subsystem: &AudioSubsystem) -> Vec<String> {
+    (0..audio_subsystem.num_audio_playback_devices().unwrap_or(0))
+        .map(|index| {
+            audio_subsystem
+                .audio_playback_device_name(index)
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+// Name of the audio output device `device` refers to, by name or by index into `devices`.
+fn find_audio_device<'a>(device: &str, devices: &'a [String]) -> Option<&'a str> {
+    devices
+        .iter()
+        .find(|name| *name == device)
+        .or_else(|| {
+            device
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| devices.get(index))
+        })
+        .map(String::as_str)
+}
+
+// FFmpeg sample format of the SDL audio format `format`.
+fn sample_format(format: AudioFormat) -> Sample {
+    match format {
+        AudioFormat::U8 => Sample::U8(sample::Type::Packed),
+        AudioFormat::S32LSB | AudioFormat::S32MSB => Sample::I32(sample::Type::Packed),
+        AudioFormat::F32LSB | AudioFormat::F32MSB => Sample::F32(sample::Type::Packed),
+        _ => Sample::I16(sample::Type::Packed),
+    }
 }
 
 fn sdl_init(
     window_width: u32,
     window_height: u32,
-) -> Result<(WindowCanvas, EventPump), FFplayError> {
+    allow_highdpi: bool,
+) -> Result<(WindowCanvas, EventPump, Option<AudioSubsystem>), FFplayError> {
     let sdl_context = sdl2::init()
         .map_err(SDL2Error::Init)
         .into_report()
@@ -106,12 +344,12 @@ fn sdl_init(
         .change_context(FFplayError)?;
 
     info!("create window with {}x{}", window_width, window_height);
-    let window = video_subsystem
-        .window("ffplay", window_width, window_height)
-        .resizable()
-        .position_centered()
-        .maximized()
-        .allow_highdpi()
+    let mut window_builder = video_subsystem.window("ffplay", window_width, window_height);
+    window_builder.resizable().position_centered().maximized();
+    if allow_highdpi {
+        window_builder.allow_highdpi();
+    }
+    let window = window_builder
         .build()
         .map_err(SDL2Error::WindowBuild)
         .into_report()
@@ -132,52 +370,523 @@ fn sdl_init(
         .into_report()
         .change_context(FFplayError)?;
 
-    Ok((canvas, event_pump))
+    // Without audio the video still plays.
+    let audio_subsystem = sdl_context
+        .audio()
+        .map_err(|err| warn!("cannot init audio: {}", err))
+        .ok();
+
+    Ok((canvas, event_pump, audio_subsystem))
 }
 
-fn av_to_sdl_pixel_format_mapper(fmt: &format::Pixel) -> PixelFormatEnum {
-    match fmt {
-        format::Pixel::YUV420P => PixelFormatEnum::IYUV,
-        format::Pixel::YUYV422 => PixelFormatEnum::YUY2,
-        format::Pixel::UYVY422 => PixelFormatEnum::UYVY,
-        _ => PixelFormatEnum::Unknown,
+// Streaming texture for frames of `pixel_format` in `width` x `height`.
+fn create_video_texture(
+    texture_creator: &TextureCreator<WindowContext>,
+    pixel_format: Pixel,
+    width: u32,
+    height: u32,
+) -> Result<Texture<'_>, FFplayError> {
+    let mut texture = texture_creator
+        .create_texture_streaming(av_to_sdl_pixel_format_mapper(&pixel_format), width, height)
+        .map_err(SDL2Error::TextureValue)
+        .into_report()
+        .change_context(FFplayError)?;
+    if pixel_format == Pixel::RGBA {
+        // Without blending SDL ignores the alpha of the texture.
+        texture.set_blend_mode(BlendMode::Blend);
     }
+    Ok(texture)
 }
 
-fn main() -> Result<(), FFplayError> {
-    env_logger::init();
+// Height of the progress bar and of the area at the bottom of the window where clicks seek.
+const PROGRESS_BAR_HEIGHT: u32 = 4;
+const PROGRESS_BAR_CLICK_HEIGHT: u32 = 20;
 
-    let mut player_builder =
-        file_decoder::FileDecoderBuilder::new(env::args().nth(1).expect("Cannot open file."));
-    let mut player = player_builder
-        .pixel_format(Pixel::YUV420P)
-        .build()
+// Draw the progress bar over the bottom of the window, `fraction` of it is played.
+fn draw_progress_bar(canvas: &mut WindowCanvas, fraction: f64) -> Result<(), FFplayError> {
+    let (width, height) = canvas
+        .output_size()
+        .map_err(SDL2Error::Draw)
+        .into_report()
         .change_context(FFplayError)?;
-    //.map_err(FFplayError::PlayerError)?;
+    // The video viewport is letterboxed, the bar spans the whole window.
+    let viewport = canvas.viewport();
+    canvas.set_viewport(None);
+    let played_width = (width as f64 * fraction.clamp(0.0, 1.0)).round() as u32;
+    let y = height.saturating_sub(PROGRESS_BAR_HEIGHT) as i32;
+    let bars = [
+        (Color::RGB(64, 64, 64), 0, width),
+        (Color::RGB(255, 255, 255), 0, played_width),
+    ];
+    for (color, x, bar_width) in bars {
+        if bar_width == 0 {
+            continue;
+        }
+        canvas.set_draw_color(color);
+        canvas
+            .fill_rect(sdl2::rect::Rect::new(x, y, bar_width, PROGRESS_BAR_HEIGHT))
+            .map_err(SDL2Error::Draw)
+            .into_report()
+            .change_context(FFplayError)?;
+    }
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.set_viewport(viewport);
+    Ok(())
+}
 
-    player.init().change_context(FFplayError)?;
-    player.start().change_context(FFplayError)?;
+// Size (in pixels) of the overlay text and the space around it.
+const FONT_SIZE: u16 = 24;
+const TEXT_MARGIN: u32 = 10;
+const TEXT_PADDING: u32 = 6;
+
+// Format `ms` as "MM:SS", or "H:MM:SS" from an hour on.
+fn format_time(ms: u64) -> String {
+    let seconds = ms / 1000;
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+// Lines of the media info overlay: codec, size and pixel format, then the frame rate and the
+// bitrate if they are known.
+fn format_media_info(media_info: &MediaInfo) -> String {
+    let mut text = format!(
+        "{} {}x{} {:?}",
+        media_info.codec_name, media_info.width, media_info.height, media_info.pixel_format
+    );
+    if media_info.frame_rate.numerator() > 0 {
+        text += &format!("\n{:.2} fps", f64::from(media_info.frame_rate));
+    }
+    if let Some(bitrate_kbps) = media_info.bitrate_kbps {
+        text += &format!("\n{} kbit/s", bitrate_kbps);
+    }
+    text
+}
+
+// Where draw_text_box() places the box in the window.
+enum TextPosition {
+    TopLeft,
+    TopRight,
+    // Centered above the progress bar.
+    BottomCenter,
+}
+
+// Draw the lines of `text` on a semi-transparent box.
+fn draw_text_box(
+    canvas: &mut WindowCanvas,
+    texture_creator: &TextureCreator<WindowContext>,
+    font: &Font,
+    text: &str,
+    position: TextPosition,
+) -> Result<(), FFplayError> {
+    // The text changes with every frame, the textures are dropped (and destroyed) on return.
+    let mut lines = Vec::new();
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        let surface = font
+            .render(line)
+            .blended(Color::RGB(255, 255, 255))
+            .map_err(|err| SDL2Error::Font(err.to_string()))
+            .into_report()
+            .change_context(FFplayError)?;
+        let texture = texture_creator
+            .create_texture_from_surface(&surface)
+            .map_err(SDL2Error::TextureValue)
+            .into_report()
+            .change_context(FFplayError)?;
+        lines.push((texture, surface.width(), surface.height()));
+    }
+    if lines.is_empty() {
+        return Ok(());
+    }
+    let text_width = lines.iter().map(|(_, width, _)| *width).max().unwrap_or(0);
+    let text_height: u32 = lines.iter().map(|(_, _, height)| height).sum();
+    let box_width = text_width + 2 * TEXT_PADDING;
+    let box_height = text_height + 2 * TEXT_PADDING;
+    let (width, height) = canvas
+        .output_size()
+        .map_err(SDL2Error::Draw)
+        .into_report()
+        .change_context(FFplayError)?;
+    let (x, y) = match position {
+        TextPosition::TopLeft => (TEXT_MARGIN, TEXT_MARGIN),
+        TextPosition::TopRight => (width.saturating_sub(box_width + TEXT_MARGIN), TEXT_MARGIN),
+        TextPosition::BottomCenter => (
+            width.saturating_sub(box_width) / 2,
+            height.saturating_sub(box_height + TEXT_MARGIN + PROGRESS_BAR_CLICK_HEIGHT),
+        ),
+    };
+
+    // Like the progress bar, the overlay is placed in the window, not in the video viewport.
+    let viewport = canvas.viewport();
+    canvas.set_viewport(None);
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    let mut result = canvas.fill_rect(sdl2::rect::Rect::new(
+        x as i32, y as i32, box_width, box_height,
+    ));
+    let mut line_y = y + TEXT_PADDING;
+    for (texture, line_width, line_height) in &lines {
+        let line_x = match position {
+            TextPosition::TopLeft | TextPosition::TopRight => x + TEXT_PADDING,
+            TextPosition::BottomCenter => x + TEXT_PADDING + (text_width - line_width) / 2,
+        };
+        result = result.and_then(|()| {
+            canvas.copy(
+                texture,
+                None,
+                sdl2::rect::Rect::new(line_x as i32, line_y as i32, *line_width, *line_height),
+            )
+        });
+        line_y += line_height;
+    }
+    canvas.set_blend_mode(BlendMode::None);
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.set_viewport(viewport);
+    result
+        .map_err(SDL2Error::Draw)
+        .into_report()
+        .change_context(FFplayError)
+}
+
+// Drop the subtitles of other serials than `serial` and the ones which ended before
+// `frame_time`, a subtitle without end time ends when the next one starts. Returns the text
+// of the subtitles shown at `frame_time`.
+fn update_subtitles(subtitles: &mut Vec<SubtitleData>, serial: u64, frame_time: u64) -> String {
+    subtitles.retain(|subtitle| subtitle.serial == serial);
+    let ended: Vec<bool> = subtitles
+        .iter()
+        .enumerate()
+        .map(|(n, subtitle)| {
+            subtitle
+                .end_time
+                .or_else(|| subtitles.get(n + 1).map(|next| next.start_time))
+                .map_or(false, |end_time| end_time <= frame_time)
+        })
+        .collect();
+    let mut ended = ended.into_iter();
+    subtitles.retain(|_| !ended.next().unwrap_or(false));
+    subtitles
+        .iter()
+        .filter(|subtitle| subtitle.start_time <= frame_time)
+        .map(|subtitle| subtitle.text.as_str())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+fn draw_test_pattern(canvas: &mut WindowCanvas) -> Result<(), FFplayError> {
+    const BARS: [(u8, u8, u8); 7] = [
+        (192, 192, 192),
+        (192, 192, 0),
+        (0, 192, 192),
+        (0, 192, 0),
+        (192, 0, 192),
+        (192, 0, 0),
+        (0, 0, 192),
+    ];
+
+    let (width, height) = canvas
+        .output_size()
+        .map_err(SDL2Error::Draw)
+        .into_report()
+        .change_context(FFplayError)?;
+    let bar_width = width / BARS.len() as u32 + 1;
+    for (i, (r, g, b)) in BARS.iter().enumerate() {
+        canvas.set_draw_color(Color::RGB(*r, *g, *b));
+        canvas
+            .fill_rect(sdl2::rect::Rect::new(
+                i as i32 * bar_width as i32,
+                0,
+                bar_width,
+                height,
+            ))
+            .map_err(SDL2Error::Draw)
+            .into_report()
+            .change_context(FFplayError)?;
+    }
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.present();
+    Ok(())
+}
+
+// Size of the video on screen. Anamorphic frames are stretched to the display width. The
+// frames are rotated when copied to the canvas, 90 and 270 degrees swap the size.
+fn video_display_size(player: &FileDecoder) -> (u32, u32) {
+    let display_width =
+        (player.height() as f64 * f64::from(player.display_aspect_ratio())).round() as u32;
+    if player.rotation() % 180 == 90 {
+        (player.height(), display_width)
+    } else {
+        (display_width, player.height())
+    }
+}
+
+// Build the player. Inputs it cannot play at all end with a short message instead of the
+// error report.
+fn build_player(
+    player_builder: &FileDecoderBuilder,
+    uri: &str,
+) -> Result<FileDecoder, FFplayError> {
+    match player_builder.build() {
+        Err(report) if report.contains::<NoVideoStream>() => {
+            eprintln!(
+                "ffplay: {} has no video stream, audio-only inputs cannot be played",
+                uri
+            );
+            std::process::exit(1);
+        }
+        result => result.change_context(FFplayError),
+    }
+}
+
+// Drain the video queue without presenting the frames. Audio is disabled, nothing would
+// drain the audio queue.
+fn run_benchmark(player_builder: &mut FileDecoderBuilder, uri: &str) -> Result<(), FFplayError> {
+    let mut player = build_player(player_builder.disable_audio(true), uri)?;
+    // build() opened the input already, the time covers the decoding.
+    let started = Instant::now();
+    let frames = null_output::drain(&mut player).change_context(FFplayError)?;
+    let elapsed = started.elapsed();
+
+    println!(
+        "decoded {} frames in {:.3}s, {:.1} fps",
+        frames,
+        elapsed.as_secs_f64(),
+        frames as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    );
+    Ok(())
+}
+
+fn main() -> Result<(), FFplayError> {
+    env_logger::init();
+
+    // Prints the usage and exits with code 2 on invalid arguments, e.g. without input.
+    let args = Args::parse();
+    let mut playlist = Playlist::from_inputs(&args.input);
+    let mut uri = match playlist.current() {
+        Some(uri) => uri.to_owned(),
+        None => {
+            eprintln!("ffplay: no input to play");
+            std::process::exit(1);
+        }
+    };
 
     let def_window_width: u32 = 1920;
     let def_window_height: u32 = 1080;
+    // Pixel::RGBA blends the video with its alpha and the opacity over the background.
+    let pixel_format = args
+        .pixel_format
+        .map_or(Pixel::YUV420P, displayable_pixel_format);
+    if matches!(args.pixel_format, Some(requested) if requested != pixel_format) {
+        warn!(
+            "{:?} cannot be displayed, decode to {:?} instead",
+            args.pixel_format, pixel_format
+        );
+    }
+    let opacity = args.opacity;
 
-    let (mut canvas, mut event_pump) = sdl_init(def_window_width, def_window_height)?;
+    let mut player_builder = FileDecoderBuilder::new(uri.clone());
+    player_builder
+        .pixel_format(pixel_format)
+        .disable_audio(args.no_audio);
+    if args.pixel_format.is_none() {
+        player_builder.auto_pixel_format(supported_pixel_formats());
+    }
+    if let Some(start_at) = args.start_at {
+        player_builder.start_at(start_at);
+    }
+    if let Some(timeout) = args.timeout {
+        player_builder.open_timeout(timeout);
+    }
+    player_builder
+        .subtitles(args.subtitles)
+        .burn_in_subtitles(args.burn_in_subtitles)
+        .volume_curve(if args.linear_volume {
+            VolumeCurve::Linear
+        } else {
+            VolumeCurve::Logarithmic
+        })
+        .output_size(args.output_size)
+        .deinterlace(args.deinterlace)
+        .accurate_seek(args.accurate_seek);
+    if let Some(subtitle_stream) = args.subtitle_stream {
+        player_builder.subtitle_stream_index(subtitle_stream);
+    }
+    if let Some(sync_mode) = args.sync {
+        player_builder.sync_mode(sync_mode);
+    }
+    if args.benchmark {
+        return run_benchmark(&mut player_builder, &uri);
+    }
+
+    let (mut canvas, mut event_pump, audio_subsystem) =
+        sdl_init(def_window_width, def_window_height, !args.no_highdpi)?;
+    if args.test_pattern {
+        canvas
+            .window_mut()
+            .set_title(&format!("ffplay - loading {}…", uri))
+            .map_err(|err| SDL2Error::WindowTitle(err.to_string()))
+            .into_report()
+            .change_context(FFplayError)?;
+        draw_test_pattern(&mut canvas)?;
+    }
+    // The time overlay needs a font, playback works without it.
+    let ttf_context = sdl2::ttf::init()
+        .map_err(|err| warn!("cannot init SDL2_ttf: {}", err))
+        .ok();
+    let font = ttf_context.as_ref().and_then(|ttf_context| {
+        ttf_context
+            .load_font(&args.font, FONT_SIZE)
+            .map_err(|err| warn!("cannot load font {}: {}", args.font.display(), err))
+            .ok()
+    });
+
+    debug!("supported pixel formats {:?}", supported_pixel_formats());
+
+    let mut player = build_player(&player_builder, &uri)?;
+    //.map_err(FFplayError::PlayerError)?;
+
+    player.init().change_context(FFplayError)?;
+    player.set_loop(args.looping);
+
+    // The audio is played by a SDL queue, which the main loop keeps filled with about
+    // AUDIO_BUFFER_MS of audio. Without an audio device the video plays silently.
+    const AUDIO_BUFFER_MS: u32 = 200;
+    // Also opened if a later input of the playlist may have audio, top_up_audio gets the
+    // queue of the current input.
+    let audio_device = match &audio_subsystem {
+        Some(audio_subsystem) if player.has_audio() || playlist.has_next() => {
+            let spec = AudioSpecDesired {
+                freq: Some(FileDecoder::AUDIO_SAMPLE_RATE as i32),
+                channels: Some(FileDecoder::AUDIO_CHANNELS as u8),
+                samples: None,
+            };
+            let devices = audio_output_devices(audio_subsystem);
+            debug!("audio output devices {:?}", devices);
+            let device_name = args.audio_device.as_deref().and_then(|device| {
+                let name = find_audio_device(device, &devices);
+                if name.is_none() {
+                    warn!(
+                        "no audio device {} in {:?}, use the default one",
+                        device, devices
+                    );
+                }
+                name
+            });
+            match audio_subsystem.open_queue::<i16, _>(device_name, &spec) {
+                Ok(audio_device) => {
+                    let spec = audio_device.spec();
+                    player.set_audio_device_params(AudioParams {
+                        sample_rate: spec.freq as u32,
+                        sample_format: sample_format(spec.format),
+                        channels: spec.channels as u16,
+                    });
+                    debug!("audio {:?}", player.audio_spec());
+                    Some(audio_device)
+                }
+                Err(err) => {
+                    warn!("cannot open audio device, play without audio: {}", err);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    if audio_device.is_none() {
+        // Nothing would take the decoded audio and the full audio queue stalls the video.
+        player.disable_audio().change_context(FFplayError)?;
+    }
+    player.start().change_context(FFplayError)?;
 
     let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture_streaming(
-            av_to_sdl_pixel_format_mapper(&player.pixel_format()),
-            player.width(),
-            player.height(),
-        )
-        .map_err(SDL2Error::TextureValue)
-        .into_report()
-        .change_context(FFplayError)?;
+    let mut texture = create_video_texture(
+        &texture_creator,
+        player.pixel_format(),
+        player.width(),
+        player.height(),
+    )?;
+    // Format and size of the texture, frames which differ get a new one.
+    let mut texture_format = (player.pixel_format(), player.width(), player.height());
+    // U and V plane for GRAY8 frames.
+    let mut neutral_chroma: Vec<u8> = Vec::new();
 
     let video_queue = player.video_queue();
 
+    let audio_frame_bytes = FileDecoder::AUDIO_CHANNELS as u32 * std::mem::size_of::<i16>() as u32;
+    let audio_buffer_bytes =
+        FileDecoder::AUDIO_SAMPLE_RATE * audio_frame_bytes * AUDIO_BUFFER_MS / 1000;
+    let audio_clock = player.audio_clock();
+    let mut audio_serial: u64 = 0;
+    // `video_ms` is the position of the video, the audio lagging behind it is dropped unless
+    // the audio is the master.
+    let mut top_up_audio = |seek_serial: u64,
+                            speed: f64,
+                            audio_queue: Option<AudioQueue>,
+                            video_ms: Option<u64>| {
+        let (audio_device, audio_queue) = match (&audio_device, &audio_queue) {
+            (Some(audio_device), Some(audio_queue)) => (audio_device, audio_queue),
+            _ => return,
+        };
+        if audio_serial != seek_serial {
+            // Drop the audio from before the seek still queued in the device.
+            audio_device.clear();
+            audio_clock.reset();
+            audio_serial = seek_serial;
+        }
+        // The clock counts in stream time, the device in played samples.
+        let buffered_samples = audio_device.size() / audio_frame_bytes;
+        audio_clock.set_buffered((buffered_samples as f64 * speed) as u64);
+        while audio_device.size() < audio_buffer_bytes {
+            let audio_data = match audio_queue.poll(Duration::ZERO) {
+                Some(audio_delay_item) => audio_delay_item.data,
+                None => break,
+            };
+            match audio_data {
+                Some(audio_data)
+                    if video_ms.map_or(false, |video_ms| {
+                        audio_is_late(audio_data.frame_time, video_ms)
+                    }) =>
+                {
+                    trace!("ffplay: drop audio behind the video");
+                }
+                Some(audio_data) if audio_data.serial == seek_serial => {
+                    if let Err(err) = audio_device.queue_audio(&audio_data.samples_at_speed(speed))
+                    {
+                        warn!("cannot queue audio: {}", err);
+                        break;
+                    }
+                    audio_clock.queued(
+                        audio_data.frame_time,
+                        (audio_data.samples.len() / FileDecoder::AUDIO_CHANNELS as usize) as u64,
+                    );
+                }
+                Some(_) => trace!("ffplay: drop audio with old serial"),
+                None => break,
+            }
+        }
+    };
+
     let handle_window_resize = |canvas: &mut WindowCanvas, video_size: (u32, u32)| {
-        let new_window_size = canvas.window().drawable_size();
+        // The viewport is in renderer pixels, which differ from the window size in points on
+        // HiDPI displays.
+        let new_window_size = match canvas.output_size() {
+            Ok(output_size) => output_size,
+            Err(err) => {
+                warn!("cannot get output size: {}", err);
+                return;
+            }
+        };
+        if new_window_size.0 == 0 || new_window_size.1 == 0 {
+            // e.g. while minimized, keep the last viewport
+            debug!("ignore drawable size {:?}", new_window_size);
+            return;
+        }
         let ratio: f64 = min(
             new_window_size.0 as f64 / video_size.0 as f64,
             new_window_size.1 as f64 / video_size.1 as f64,
@@ -216,83 +925,503 @@ fn main() -> Result<(), FFplayError> {
                     Keycode::Space => return Some(EventState::Pause),
                     Keycode::Left => return Some(EventState::SeekBackward),
                     Keycode::Right => return Some(EventState::SeekForward),
+                    Keycode::Up => return Some(EventState::NextChapter),
+                    Keycode::Down => return Some(EventState::PreviousChapter),
+                    Keycode::Period => return Some(EventState::StepForward),
+                    Keycode::K => return Some(EventState::CycleSeekStep),
+                    Keycode::LeftBracket => return Some(EventState::SpeedDown),
+                    Keycode::RightBracket => return Some(EventState::SpeedUp),
+                    Keycode::PageDown => return Some(EventState::NextKeyframe),
+                    Keycode::PageUp => return Some(EventState::PreviousKeyframe),
+                    Keycode::U => return Some(EventState::TogglePauseOnFocusLoss),
+                    Keycode::F => return Some(EventState::ToggleFullscreen),
+                    Keycode::S => return Some(EventState::Screenshot),
+                    Keycode::I => return Some(EventState::ToggleInfo),
+                    Keycode::C => return Some(EventState::ToggleMediaInfo),
+                    Keycode::T => return Some(EventState::ToggleSubtitles),
+                    Keycode::Num1 => return Some(EventState::AdjustEq(-0.05, 0.0, 0.0)),
+                    Keycode::Num2 => return Some(EventState::AdjustEq(0.05, 0.0, 0.0)),
+                    Keycode::Num3 => return Some(EventState::AdjustEq(0.0, -0.1, 0.0)),
+                    Keycode::Num4 => return Some(EventState::AdjustEq(0.0, 0.1, 0.0)),
+                    Keycode::Num5 => return Some(EventState::AdjustEq(0.0, 0.0, -0.1)),
+                    Keycode::Num6 => return Some(EventState::AdjustEq(0.0, 0.0, 0.1)),
+                    Keycode::Num0 => return Some(EventState::ResetEq),
+                    Keycode::D => return Some(EventState::PrintStats),
+                    Keycode::Plus | Keycode::KpPlus | Keycode::Equals => {
+                        return Some(EventState::AdjustVolume(10))
+                    }
+                    Keycode::Minus | Keycode::KpMinus => {
+                        return Some(EventState::AdjustVolume(-10))
+                    }
+                    Keycode::M => return Some(EventState::ToggleMute),
+                    Keycode::N => return Some(EventState::NextInput),
                     _ => return None,
                 },
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    clicks: 2,
+                    ..
+                } => return Some(EventState::ToggleFullscreen),
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => return Some(EventState::Click(x, y)),
                 Event::Window {
                     timestamp: _,
                     window_id: _,
                     win_event: WindowEvent::Resized(_, _),
                 } => return Some(EventState::Resize),
+                Event::Window {
+                    win_event: WindowEvent::Minimized,
+                    ..
+                } => return Some(EventState::Minimized),
+                Event::Window {
+                    win_event: WindowEvent::Restored,
+                    ..
+                } => return Some(EventState::Restored),
+                Event::Window {
+                    win_event: WindowEvent::FocusLost,
+                    ..
+                } => return Some(EventState::FocusLost),
+                Event::Window {
+                    win_event: WindowEvent::FocusGained,
+                    ..
+                } => return Some(EventState::FocusGained),
                 _ => return None,
             }
         }
         None
     };
 
-    let event_pumper = |wait_for_event: bool, event_pump: &mut EventPump| -> Option<EventState> {
+    // Returns all pending events, so bursts of events (e.g. resizes while dragging the
+    // window border) can be handled at once per iteration.
+    let event_pumper = |wait_for_event: bool, event_pump: &mut EventPump| -> Vec<EventState> {
+        let mut events = Vec::new();
         if wait_for_event {
-            event_transform(event_pump.wait_iter().next())
-        } else {
-            event_transform(event_pump.poll_iter().next())
+            events.extend(event_transform(event_pump.wait_iter().next()));
         }
+        events.extend(
+            event_pump
+                .poll_iter()
+                .filter_map(|event| event_transform(Some(event))),
+        );
+        events
     };
 
+    let mut rotation = player.rotation();
+    let mut display_size = video_display_size(&player);
     // Setup canvas for initial window size:
-    handle_window_resize(&mut canvas, (player.width(), player.height()));
+    handle_window_resize(&mut canvas, display_size);
 
-    let mut paused = false;
-    let mut need_update = false;
-    let mut presentation_time = Instant::now();
+    // Pause when the window loses focus and resume when it gets it back, toggled with 'u'.
+    let mut pause_on_focus_loss = false;
+    let mut paused_by_focus_loss = false;
+    // Frames are still taken and timed while minimized, but not uploaded and presented.
+    let mut minimized = false;
+    // Show the time overlay, toggled with 'i'.
+    let mut show_info = false;
+    // Show codec, size, pixel format, frame rate and bitrate, toggled with 'c'.
+    let mut show_media_info = false;
+    // Subtitles taken from the queue until they end, shown unless toggled off with 't'.
+    let mut subtitle_queue = player.subtitle_queue();
+    let mut subtitles: Vec<SubtitleData> = Vec::new();
+    let mut show_subtitles = true;
+    // Window size before switching to fullscreen, restored when leaving it.
+    let mut windowed_size: Option<(u32, u32)> = None;
+    let mut clock = PresentationClock::new(args.clock_gain);
+    // Timestamps to call back on when playback crosses them.
+    let mut marks = Marks::default();
+    marks.set_fire_on_seek(args.marks_on_seek);
+    for &time in &args.marks {
+        marks.add(time, Box::new(|time| info!("mark at {:?}", time)));
+    }
     let mut video_data_item: Option<VideoData> = None;
+    // Frame on screen, kept for screenshots.
+    let mut presented_frame: Option<VideoData> = None;
     let mut last_pts: u64 = 0;
     let mut seek_serial: u64 = 0;
-    let seek_secs: i64 = 20000;
+    let mut presented_serial: u64 = 0;
+    let mut first_frame_presented = false;
+    // One frame step requested while paused.
+    let mut step_frame = false;
+    // Switch to the next input of the playlist, at the end of the current one or with 'n'.
+    let mut next_input = false;
+    let mut seek_step = args.seek_step;
+    // Show the seek step in the overlay until then after it changed.
+    let mut seek_step_shown_until: Option<Instant> = None;
+    // Seek steps cycled through with 'k'.
+    const SEEK_STEP_PRESETS: [Duration; 4] = [
+        Duration::from_secs(5),
+        Duration::from_secs(10),
+        Duration::from_secs(20),
+        Duration::from_secs(60),
+    ];
+    // Playback speeds stepped through with '[' and ']'.
+    const SPEED_PRESETS: [f64; 8] = [0.25, 0.5, 0.75, 1.0, 1.25, 1.5, 2.0, 4.0];
     'running: loop {
+        if next_input {
+            next_input = false;
+            // The window and the pause state are kept, only the input changes.
+            match playlist.open_next(&mut player) {
+                Some(serial) => {
+                    uri = playlist.current().unwrap_or_default().to_owned();
+                    info!("play {} (serial {})", uri, serial);
+                    seek_serial = serial;
+                    last_pts = 0;
+                    first_frame_presented = false;
+                    presented_frame = None;
+                    video_data_item = None;
+                    subtitle_queue = player.subtitle_queue();
+                    subtitles.clear();
+                    clock.reset();
+                    rotation = player.rotation();
+                    display_size = video_display_size(&player);
+                    handle_window_resize(&mut canvas, display_size);
+                }
+                None => {
+                    // Inputs which could not be opened were skipped, the player is stopped.
+                    trace!("ffplay: no next input, break running");
+                    break 'running;
+                }
+            }
+        }
         canvas.clear();
-        if let Some(event) = event_pumper(paused && !need_update, &mut event_pump) {
+        if let Some(audio_device) = &audio_device {
+            if player.is_paused() {
+                audio_device.pause();
+            } else {
+                audio_device.resume();
+            }
+        }
+        let wait_for_event = player.is_paused() && !player.needs_refresh() && !step_frame;
+        let mut skip_frame = false;
+        let mut resized = false;
+        for event in event_pumper(wait_for_event, &mut event_pump) {
             match event {
                 EventState::Quit => break 'running,
                 EventState::Pause => {
-                    if paused {
-                        presentation_time = Instant::now();
+                    let paused = !player.is_paused();
+                    if !paused {
+                        clock.reset();
                     }
-                    paused = !paused;
+                    player.set_paused(paused);
+                    paused_by_focus_loss = false;
                     debug!("space pressed paused={}", paused);
-                    continue 'running;
+                    skip_frame = true;
                 }
-                EventState::SeekBackward => {
-                    let seek_to = last_pts as i64 - seek_secs;
-                    debug!("seek to {} (last_pts={})", seek_to, last_pts);
-                    last_pts = seek_to as u64;
-                    seek_serial = player.seek(seek_to).change_context(FFplayError)?;
-                    need_update = true;
-                    debug!("seek to {} (serial {})", seek_to, seek_serial);
-                    continue 'running;
+                EventState::FocusLost => {
+                    if pause_on_focus_loss && !player.is_paused() {
+                        debug!("focus lost, pause");
+                        player.set_paused(true);
+                        paused_by_focus_loss = true;
+                    }
+                    skip_frame = true;
                 }
-                EventState::SeekForward => {
-                    let seek_to = last_pts as i64 + seek_secs;
-                    debug!("seek to {} (last_pts={})", seek_to, last_pts);
-                    last_pts = seek_to as u64;
-                    seek_serial = player.seek(seek_to).change_context(FFplayError)?;
-                    need_update = true;
-                    debug!("seek to {} (serial {})", seek_to, seek_serial);
-                    continue 'running;
+                EventState::FocusGained => {
+                    if paused_by_focus_loss {
+                        debug!("focus gained, resume");
+                        clock.reset();
+                        player.set_paused(false);
+                        paused_by_focus_loss = false;
+                    }
+                    skip_frame = true;
+                }
+                EventState::TogglePauseOnFocusLoss => {
+                    pause_on_focus_loss = !pause_on_focus_loss;
+                    debug!("pause on focus loss={}", pause_on_focus_loss);
+                    skip_frame = true;
+                }
+                EventState::SeekBackward
+                | EventState::SeekForward
+                | EventState::NextChapter
+                | EventState::PreviousChapter => {
+                    let position = Duration::from_millis(last_pts);
+                    let forward =
+                        matches!(event, EventState::SeekForward | EventState::NextChapter);
+                    let by_chapter =
+                        matches!(event, EventState::NextChapter | EventState::PreviousChapter);
+                    let seek_to = if by_chapter && !player.chapters().is_empty() {
+                        let chapter = if forward {
+                            player.next_chapter(position)
+                        } else {
+                            player.previous_chapter(position)
+                        };
+                        // Past the last chapter the end is the boundary, before the first one
+                        // the start.
+                        chapter.unwrap_or_else(|| {
+                            if forward {
+                                player
+                                    .duration()
+                                    .map_or(position, |duration| player.start_time() + duration)
+                            } else {
+                                player.start_time()
+                            }
+                        })
+                    } else if forward {
+                        position + seek_step
+                    } else {
+                        position.saturating_sub(seek_step)
+                    };
+                    // The decoder clamps the target to the end as well, keep last_pts there.
+                    let seek_to = player.duration().map_or(seek_to, |duration| {
+                        seek_to.min(player.start_time() + duration)
+                    });
+                    debug!("seek to {:?} (last_pts={})", seek_to, last_pts);
+                    seek_serial = player.seek_to(seek_to).change_context(FFplayError)?;
+                    last_pts = seek_to.as_millis() as u64;
+                    debug!("seek to {:?} (serial {})", seek_to, seek_serial);
+                    skip_frame = true;
+                }
+                EventState::CycleSeekStep => {
+                    seek_step = SEEK_STEP_PRESETS
+                        .iter()
+                        .find(|preset| **preset > seek_step)
+                        .copied()
+                        .unwrap_or(SEEK_STEP_PRESETS[0]);
+                    info!("seek step {:?}", seek_step);
+                    seek_step_shown_until = Some(Instant::now() + Duration::from_secs(2));
+                    skip_frame = true;
+                }
+                EventState::StepForward => {
+                    // Present exactly one more frame and stay paused, unlike the refresh after
+                    // a seek this does not depend on the serial.
+                    if !player.is_paused() {
+                        player.set_paused(true);
+                        paused_by_focus_loss = false;
+                    }
+                    debug!("step to next frame");
+                    step_frame = true;
+                    skip_frame = true;
+                }
+                EventState::SpeedDown | EventState::SpeedUp => {
+                    let speed = player.speed();
+                    let preset = if matches!(event, EventState::SpeedUp) {
+                        SPEED_PRESETS
+                            .iter()
+                            .find(|preset| **preset > speed + f64::EPSILON)
+                    } else {
+                        SPEED_PRESETS
+                            .iter()
+                            .rev()
+                            .find(|preset| **preset < speed - f64::EPSILON)
+                    };
+                    if let Some(preset) = preset {
+                        player.set_speed(*preset);
+                        // The frame times no longer match the wall clock pace, restart it.
+                        clock.reset();
+                        info!("playback speed {}x", player.speed());
+                    }
+                }
+                EventState::NextKeyframe => {
+                    seek_serial = player.seek_to_next_keyframe().change_context(FFplayError)?;
+                    debug!("seek to next keyframe (serial {})", seek_serial);
+                    skip_frame = true;
+                }
+                EventState::PreviousKeyframe => {
+                    seek_serial = player
+                        .seek_to_previous_keyframe()
+                        .change_context(FFplayError)?;
+                    debug!("seek to previous keyframe (serial {})", seek_serial);
+                    skip_frame = true;
+                }
+                EventState::Resize => resized = true,
+                EventState::ToggleFullscreen => {
+                    let window = canvas.window_mut();
+                    if window.fullscreen_state() == FullscreenType::Off {
+                        windowed_size = Some(window.size());
+                        window
+                            .set_fullscreen(FullscreenType::Desktop)
+                            .map_err(SDL2Error::Fullscreen)
+                            .into_report()
+                            .change_context(FFplayError)?;
+                    } else {
+                        window
+                            .set_fullscreen(FullscreenType::Off)
+                            .map_err(SDL2Error::Fullscreen)
+                            .into_report()
+                            .change_context(FFplayError)?;
+                        if let Some((width, height)) = windowed_size.take() {
+                            if let Err(err) = window.set_size(width, height) {
+                                warn!("cannot restore window size: {}", err);
+                            }
+                        }
+                    }
+                    debug!("fullscreen {:?}", window.fullscreen_state());
+                    resized = true;
+                }
+                EventState::Click(x, y) => {
+                    let (window_width, window_height) = canvas.window().size();
+                    let on_progress_bar =
+                        y >= window_height.saturating_sub(PROGRESS_BAR_CLICK_HEIGHT) as i32;
+                    if on_progress_bar && window_width > 0 && player.duration().is_some() {
+                        let fraction = x as f64 / window_width as f64;
+                        // The target is in the time base of the stream, last_pts is resynced
+                        // to the frame the seek lands on.
+                        let (serial, _) =
+                            player.seek_fraction(fraction).change_context(FFplayError)?;
+                        seek_serial = serial;
+                        debug!("seek to {:.3} (serial {})", fraction, seek_serial);
+                        skip_frame = true;
+                    }
+                }
+                EventState::Screenshot => match &presented_frame {
+                    Some(video_data) => {
+                        // The frame time keeps screenshots of different frames apart.
+                        let file_name = format!("ffplay-{}ms.png", video_data.frame_time);
+                        match screenshot::save_png(&video_data.video_frame, Path::new(&file_name)) {
+                            Ok(()) => info!("saved screenshot {}", file_name),
+                            Err(err) => warn!("cannot save screenshot: {:?}", err),
+                        }
+                    }
+                    None => debug!("no frame presented yet, no screenshot"),
+                },
+                EventState::ToggleInfo => {
+                    show_info = !show_info;
+                    if show_info && font.is_none() {
+                        warn!("no font loaded, cannot show the time overlay");
+                    }
+                }
+                EventState::ToggleMediaInfo => {
+                    show_media_info = !show_media_info;
+                    if show_media_info && font.is_none() {
+                        warn!("no font loaded, cannot show the media info");
+                    }
+                }
+                EventState::ToggleSubtitles => {
+                    show_subtitles = !show_subtitles;
+                    debug!("show subtitles {}", show_subtitles);
+                }
+                EventState::AdjustEq(brightness, contrast, saturation) => {
+                    let eq = player.eq();
+                    player.set_eq(EqSettings {
+                        brightness: eq.brightness + brightness,
+                        contrast: eq.contrast + contrast,
+                        saturation: eq.saturation + saturation,
+                    });
+                    info!("{:?}", player.eq());
+                }
+                EventState::ResetEq => {
+                    player.set_eq(EqSettings::default());
+                    info!("{:?}", player.eq());
+                }
+                EventState::AdjustVolume(step) => {
+                    let volume = (player.volume() as i32 + step).max(0) as u32;
+                    player.set_volume(volume);
+                    info!("volume {} ({:.1} dB)", player.volume(), player.volume_db());
+                }
+                EventState::ToggleMute => {
+                    player.set_muted(!player.is_muted());
+                    info!("muted {}", player.is_muted());
+                }
+                EventState::PrintStats => {
+                    let stats = player.stats();
+                    info!(
+                        "frames decoded {}, displayed {}, dropped {}, repeated {}, queued {}",
+                        stats.decoded_frames,
+                        stats.displayed_frames,
+                        stats.dropped_frames,
+                        stats.repeated_frames,
+                        stats.queue_depth
+                    );
+                }
+                EventState::Minimized => {
+                    debug!("window minimized");
+                    minimized = true;
+                }
+                EventState::Restored => {
+                    debug!("window restored");
+                    minimized = false;
+                    resized = true;
+                }
+                EventState::NextInput => {
+                    if playlist.has_next() {
+                        next_input = true;
+                        skip_frame = true;
+                    } else {
+                        info!("{} is the last input", uri);
+                    }
+                }
+            }
+        }
+
+        if resized {
+            handle_window_resize(&mut canvas, display_size);
+        }
+
+        while let Some(decoder_event) = player.try_recv_event() {
+            match decoder_event {
+                DecoderEvent::SeekRequested { serial, target } => {
+                    debug!("seek {} to {:?} requested", serial, target)
+                }
+                DecoderEvent::SeekKeyframeReached { serial, position } => {
+                    debug!("seek {} reached keyframe at {:?}", serial, position)
                 }
-                EventState::Resize => {
-                    handle_window_resize(&mut canvas, (player.width(), player.height()));
+                DecoderEvent::SeekCompleted {
+                    serial,
+                    target,
+                    position,
+                } => debug!(
+                    "seek {} to {:?} completed at {:?}",
+                    serial, target, position
+                ),
+                DecoderEvent::EndOfStream { serial } => {
+                    debug!("end of stream with serial {}", serial)
                 }
             }
         }
+        if skip_frame {
+            continue 'running;
+        }
 
-        if paused && !need_update {
+        if player.is_paused() && !player.needs_refresh() && !step_frame {
             continue 'running;
         }
 
+        // The queue ran empty during playback, the current frame stays on screen until the
+        // decoder catches up.
+        let mut underrun = false;
         if video_data_item.is_none() {
+            underrun = video_queue.is_empty()
+                && first_frame_presented
+                && presented_serial == seek_serial
+                && !player.is_paused();
             trace!("ffplay: get from video queue");
-            video_data_item = video_queue.take().data;
+            // Keep the audio going while waiting, the demuxer needs both queues drained.
+            video_data_item = loop {
+                top_up_audio(
+                    seek_serial,
+                    player.speed(),
+                    player.audio_queue(),
+                    (player.sync_mode() != SyncMode::AudioMaster).then_some(last_pts),
+                );
+                if let Some(video_delay_item) = video_queue.poll(Duration::from_millis(10)) {
+                    break video_delay_item.data;
+                }
+                // A failed thread does not deliver any more frames.
+                if let Some(err) = player.try_take_error() {
+                    return Err(err.change_context(FFplayError));
+                }
+            };
             trace!("ffplay: return from get in video queue");
             if video_data_item.is_none() {
+                // Distinguish a failure from a clean end of the input.
+                if let Some(err) = player.try_take_error() {
+                    return Err(err.change_context(FFplayError));
+                }
+                if let Some(serial) = player.restart_loop().change_context(FFplayError)? {
+                    debug!("loop to start (serial {})", serial);
+                    seek_serial = serial;
+                    // Restart the pacing, the frames from the start must not be presented
+                    // right away to catch up.
+                    clock.reset();
+                    continue 'running;
+                }
+                if playlist.has_next() {
+                    next_input = true;
+                    continue 'running;
+                }
                 trace!("ffplay: item is none, break running");
                 break 'running;
             }
@@ -301,7 +1430,12 @@ fn main() -> Result<(), FFplayError> {
         let video_data = video_data_item.unwrap();
 
         if video_data.serial == seek_serial {
-            let now = Instant::now();
+            if video_data.is_seek_landing {
+                debug!(
+                    "landed at {} (requested {}, serial={})",
+                    video_data.frame_time, last_pts, seek_serial
+                );
+            }
             trace!(
                 "change last pts from {} to {} (serial={})",
                 last_pts,
@@ -309,71 +1443,259 @@ fn main() -> Result<(), FFplayError> {
                 seek_serial
             );
             last_pts = video_data.frame_time;
-            let frame_time = Duration::from_millis(video_data.diff_to_prev_frame);
-            if presentation_time + frame_time > now {
-                let sleep_time = presentation_time + frame_time - now;
-                trace!("ffplay: sleep for {:?}", sleep_time);
-                thread::sleep(presentation_time + frame_time - now);
+            let seeked = presented_serial != video_data.serial;
+            if seeked {
+                // First frame after a seek, restart the presentation clock from here.
+                clock.reset();
+                presented_serial = video_data.serial;
             }
-            presentation_time += frame_time;
-
-            if video_data.video_frame.planes() == 1 {
-                texture
-                    .update(
-                        None,
-                        video_data.video_frame.data(0),
-                        video_data.video_frame.stride(0),
-                    )
-                    .map_err(SDL2Error::TextureUpdate)
-                    .into_report()
-                    .change_context(FFplayError)?;
-            } else if video_data.video_frame.planes() == 2 {
-                let y_plane = video_data.video_frame.data(0);
-                let y_stride = video_data.video_frame.stride(0);
-                let u_plane = video_data.video_frame.data(1);
-                let u_stride = video_data.video_frame.stride(1);
-                let v_plane = video_data.video_frame.data(2);
-                let v_stride = video_data.video_frame.stride(2);
-
-                texture
-                    .update_yuv(
-                        None, y_plane, y_stride, u_plane, u_stride, v_plane, v_stride,
-                    )
-                    .map_err(SDL2Error::TextureUpdateYUV)
-                    .into_report()
-                    .change_context(FFplayError)?;
+            top_up_audio(
+                seek_serial,
+                player.speed(),
+                player.audio_queue(),
+                (player.sync_mode() != SyncMode::AudioMaster).then_some(last_pts),
+            );
+            let now = Instant::now();
+            let action = if player.is_paused() {
+                // Stepping or refreshing after a seek while paused, present right away.
+                FrameAction::Present(now)
             } else {
-                assert!(video_data.video_frame.planes() == 3);
-
-                let y_plane = video_data.video_frame.data(0);
-                let y_stride = video_data.video_frame.stride(0);
-                let u_plane = video_data.video_frame.data(1);
-                let u_stride = video_data.video_frame.stride(1);
-                let v_plane = video_data.video_frame.data(2);
-                let v_stride = video_data.video_frame.stride(2);
-
-                texture
-                    .update_yuv(
-                        None, y_plane, y_stride, u_plane, u_stride, v_plane, v_stride,
-                    )
-                    .map_err(SDL2Error::TextureUpdateYUV)
-                    .into_report()
-                    .change_context(FFplayError)?;
+                match player.master_clock_ms() {
+                    Some(master_ms) => {
+                        schedule_against(video_data.frame_time, master_ms, player.speed(), now)
+                    }
+                    // The video is the master, present every frame at its own pace.
+                    None if player.sync_mode() == SyncMode::VideoMaster => {
+                        FrameAction::Present(clock.schedule(
+                            video_data.frame_time,
+                            video_data.diff_to_prev_frame,
+                            player.speed(),
+                            now,
+                        ))
+                    }
+                    // Without audio playing pace the video by the wall clock.
+                    None => {
+                        let present_at = clock.schedule(
+                            video_data.frame_time,
+                            video_data.diff_to_prev_frame,
+                            player.speed(),
+                            now,
+                        );
+                        // Later than a frame interval, the decoder falls behind. Presenting
+                        // it would only delay the following frames.
+                        let frame_interval = Duration::from_secs_f64(
+                            video_data.diff_to_prev_frame as f64 / player.speed() / 1000.0,
+                        );
+                        if now.saturating_duration_since(present_at) > frame_interval {
+                            FrameAction::Drop
+                        } else {
+                            FrameAction::Present(present_at)
+                        }
+                    }
+                }
+            };
+            let present_at = match action {
+                FrameAction::Present(present_at) => present_at,
+                FrameAction::Drop => {
+                    player.frame_dropped();
+                    debug!(
+                        "ffplay: dropped late frame {} ({} dropped)",
+                        video_data.frame_time,
+                        player.stats().dropped_frames
+                    );
+                    video_data_item = None;
+                    continue 'running;
+                }
+            };
+            if underrun && present_at < now {
+                trace!("ffplay: video queue underrun, repeated frame");
+                player.frame_repeated();
             }
+            if present_at > now {
+                let sleep_time = present_at - now;
+                trace!("ffplay: sleep for {:?}", sleep_time);
+                thread::sleep(sleep_time);
+            }
+
+            if minimized {
+                trace!("ffplay: skip frame while minimized");
+            } else {
+                // The size can change mid-stream, e.g. with adaptive streams or spliced
+                // content.
+                let frame = &video_data.video_frame;
+                let frame_format = (frame.format(), frame.width(), frame.height());
+                if frame_format != texture_format {
+                    debug!(
+                        "frame format changed from {:?} to {:?}, recreate the texture",
+                        texture_format, frame_format
+                    );
+                    texture = create_video_texture(
+                        &texture_creator,
+                        frame_format.0,
+                        frame_format.1,
+                        frame_format.2,
+                    )?;
+                    texture_format = frame_format;
+                }
+                if frame.format() == Pixel::GRAY8 {
+                    // Luma only, shown on a YUV texture with neutral chroma.
+                    let chroma_width = (frame.width() as usize + 1) / 2;
+                    let chroma_len = chroma_width * ((frame.height() as usize + 1) / 2);
+                    if neutral_chroma.len() != chroma_len {
+                        neutral_chroma = vec![128; chroma_len];
+                    }
+                    texture
+                        .update_yuv(
+                            None,
+                            frame.data(0),
+                            frame.stride(0),
+                            &neutral_chroma,
+                            chroma_width,
+                            &neutral_chroma,
+                            chroma_width,
+                        )
+                        .map_err(SDL2Error::TextureUpdateYUV)
+                        .into_report()
+                        .change_context(FFplayError)?;
+                } else if video_data.video_frame.planes() == 1 {
+                    texture
+                        .update(
+                            None,
+                            video_data.video_frame.data(0),
+                            video_data.video_frame.stride(0),
+                        )
+                        .map_err(SDL2Error::TextureUpdate)
+                        .into_report()
+                        .change_context(FFplayError)?;
+                } else if video_data.video_frame.planes() == 2 {
+                    texture
+                        .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                            copy_nv12(&video_data.video_frame, buffer, pitch)
+                        })
+                        .map_err(SDL2Error::TextureLock)
+                        .into_report()
+                        .change_context(FFplayError)?;
+                } else {
+                    assert!(video_data.video_frame.planes() == 3);
+
+                    let y_plane = video_data.video_frame.data(0);
+                    let y_stride = video_data.video_frame.stride(0);
+                    let u_plane = video_data.video_frame.data(1);
+                    let u_stride = video_data.video_frame.stride(1);
+                    let v_plane = video_data.video_frame.data(2);
+                    let v_stride = video_data.video_frame.stride(2);
+
+                    texture
+                        .update_yuv(
+                            None, y_plane, y_stride, u_plane, u_stride, v_plane, v_stride,
+                        )
+                        .map_err(SDL2Error::TextureUpdateYUV)
+                        .into_report()
+                        .change_context(FFplayError)?;
+                }
 
-            canvas
-                .copy(&texture, None, None)
+                if texture_format.0 == Pixel::RGBA {
+                    texture.set_alpha_mod((opacity * 255.0).round() as u8);
+                }
+                if rotation == 0 {
+                    canvas.copy(&texture, None, None)
+                } else {
+                    // Rotated around its center, so the unrotated frame is centered in the
+                    // viewport.
+                    let viewport = canvas.viewport();
+                    let (width, height) = if rotation % 180 == 90 {
+                        (viewport.height(), viewport.width())
+                    } else {
+                        (viewport.width(), viewport.height())
+                    };
+                    let dst = sdl2::rect::Rect::new(
+                        (viewport.width() as i32 - width as i32) / 2,
+                        (viewport.height() as i32 - height as i32) / 2,
+                        width,
+                        height,
+                    );
+                    canvas.copy_ex(&texture, None, dst, rotation as f64, None, false, false)
+                }
                 .map_err(SDL2Error::CopyTextureToCanvas)
                 .into_report()
                 .change_context(FFplayError)?;
+            }
 
             trace!(
                 "ffplay: present frame with pts {}",
                 video_data.video_frame.pts().unwrap_or_default()
             );
-            need_update = false;
+            player.frame_presented(&video_data);
+            step_frame = false;
+            marks.update(Duration::from_millis(video_data.frame_time), seeked);
 
-            canvas.present();
+            if let Some(subtitle_queue) = &subtitle_queue {
+                while let Some(subtitle_delay_item) = subtitle_queue.poll(Duration::ZERO) {
+                    subtitles.extend(subtitle_delay_item.data);
+                }
+            }
+            let subtitle_text =
+                update_subtitles(&mut subtitles, seek_serial, video_data.frame_time);
+
+            if !minimized {
+                // Positions count from the start time, which is not zero for e.g. MPEG-TS.
+                let position = last_pts.saturating_sub(player.start_time().as_millis() as u64);
+                if let Some(duration) = player.duration() {
+                    let fraction = position as f64 / duration.as_millis().max(1) as f64;
+                    draw_progress_bar(&mut canvas, fraction)?;
+                }
+                if let (true, Some(font)) = (show_info, &font) {
+                    let duration = player.duration().map_or_else(
+                        || "--:--".to_string(),
+                        |duration| format_time(duration.as_millis() as u64),
+                    );
+                    let mut text = format!("{} / {}", format_time(position), duration);
+                    if matches!(seek_step_shown_until, Some(until) if until > Instant::now()) {
+                        text += &format!("\nSeek step: {}s", seek_step.as_secs_f64());
+                    }
+                    draw_text_box(
+                        &mut canvas,
+                        &texture_creator,
+                        font,
+                        &text,
+                        TextPosition::TopLeft,
+                    )?;
+                }
+                if let (true, Some(font), Some(media_info)) =
+                    (show_media_info, &font, player.media_info())
+                {
+                    draw_text_box(
+                        &mut canvas,
+                        &texture_creator,
+                        font,
+                        &format_media_info(media_info),
+                        TextPosition::TopRight,
+                    )?;
+                }
+                if let (true, Some(font)) = (show_subtitles, &font) {
+                    draw_text_box(
+                        &mut canvas,
+                        &texture_creator,
+                        font,
+                        &subtitle_text,
+                        TextPosition::BottomCenter,
+                    )?;
+                }
+                canvas.present();
+            }
+
+            if !first_frame_presented {
+                first_frame_presented = true;
+                if args.test_pattern {
+                    canvas
+                        .window_mut()
+                        .set_title(&format!("ffplay - {}", uri))
+                        .map_err(|err| SDL2Error::WindowTitle(err.to_string()))
+                        .into_report()
+                        .change_context(FFplayError)?;
+                }
+            }
+            presented_frame = Some(video_data);
         } else {
             trace!("ffplay: got frame with old serial");
         }
@@ -381,7 +1703,104 @@ fn main() -> Result<(), FFplayError> {
         video_data_item = None;
     }
 
+    let stats = player.stats();
+    debug!(
+        "bitrate declared {:?} kbps, measured {} kbps",
+        stats.declared_bitrate_kbps, stats.measured_bitrate_kbps
+    );
+    debug!(
+        "latency open {:?}, last seek {:?}",
+        stats.open_latency, stats.seek_latency
+    );
+    debug!(
+        "frames decoded {}, displayed {}, dropped {}, repeated {}",
+        stats.decoded_frames, stats.displayed_frames, stats.dropped_frames, stats.repeated_frames
+    );
+
     player.stop();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ffmpeg_rs::Rational;
+
+    #[test]
+    fn audio_device_by_name_or_index() {
+        let devices = vec!["Speakers".to_owned(), "HDMI".to_owned()];
+        assert_eq!(find_audio_device("HDMI", &devices), Some("HDMI"));
+        assert_eq!(find_audio_device("0", &devices), Some("Speakers"));
+        assert_eq!(find_audio_device("2", &devices), None);
+        assert_eq!(find_audio_device("USB", &devices), None);
+    }
+
+    #[test]
+    fn opacity_range() {
+        assert_eq!(parse_opacity("0"), Ok(0.0));
+        assert_eq!(parse_opacity("0.5"), Ok(0.5));
+        assert!(parse_opacity("1.1").is_err());
+        assert!(parse_opacity("-0.5").is_err());
+        assert!(parse_opacity("NaN").is_err());
+    }
+
+    #[test]
+    fn sync_mode_names() {
+        assert_eq!(parse_sync_mode("audio"), Ok(SyncMode::AudioMaster));
+        assert_eq!(parse_sync_mode("video"), Ok(SyncMode::VideoMaster));
+        assert_eq!(parse_sync_mode("ext"), Ok(SyncMode::ExternalClock));
+        assert!(parse_sync_mode("external").is_err());
+    }
+
+    #[test]
+    fn clock_gain_range() {
+        assert_eq!(parse_clock_gain("0.25"), Ok(0.25));
+        assert_eq!(parse_clock_gain("1"), Ok(1.0));
+        assert!(parse_clock_gain("1.5").is_err());
+        assert!(parse_clock_gain("-0.1").is_err());
+        assert!(parse_clock_gain("NaN").is_err());
+    }
+
+    #[test]
+    fn media_info_lines() {
+        let mut media_info = MediaInfo {
+            codec_name: "h264".to_owned(),
+            width: 1920,
+            height: 1080,
+            pixel_format: Pixel::YUV420P,
+            frame_rate: Rational(30000, 1001),
+            bitrate_kbps: Some(4000),
+        };
+        assert_eq!(
+            format_media_info(&media_info),
+            "h264 1920x1080 YUV420P\n29.97 fps\n4000 kbit/s"
+        );
+        media_info.frame_rate = Rational(0, 1);
+        media_info.bitrate_kbps = None;
+        assert_eq!(format_media_info(&media_info), "h264 1920x1080 YUV420P");
+    }
+
+    #[test]
+    fn subtitles_end_at_end_time_or_next_start() {
+        let subtitle = |serial, start_time, end_time, text: &str| SubtitleData {
+            serial,
+            start_time,
+            end_time,
+            text: text.to_owned(),
+        };
+        let mut subtitles = vec![
+            subtitle(0, 500, Some(1500), "old serial"),
+            subtitle(1, 1000, None, "first"),
+            subtitle(1, 1200, Some(3000), "second"),
+            subtitle(1, 2000, Some(2500), "third"),
+        ];
+
+        assert_eq!(update_subtitles(&mut subtitles, 1, 900), "");
+        assert_eq!(update_subtitles(&mut subtitles, 1, 1100), "first");
+        assert_eq!(update_subtitles(&mut subtitles, 1, 2000), "second\nthird");
+        assert_eq!(subtitles.len(), 2);
+        assert_eq!(update_subtitles(&mut subtitles, 1, 3000), "");
+        assert!(subtitles.is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+use log::debug;
+use std::time::Duration;
+
+/// Called with the time of the crossed mark.
+pub type MarkCallback = Box<dyn FnMut(Duration) + Send>;
+
+/// Id of a mark, see [`Marks::add`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarkId(u64);
+
+struct Mark {
+    id: MarkId,
+    time: Duration,
+    callback: MarkCallback,
+}
+
+/// Timestamps which call back when the playback position crosses them.
+///
+/// The render loop reports every presented position via [`Marks::update`]. During normal
+/// playback each mark between the previous and the new position fires once. Jumping over a
+/// mark with a forward seek only fires it if enabled with [`Marks::set_fire_on_seek`],
+/// seeking backwards never fires.
+#[derive(Default)]
+pub struct Marks {
+    marks: Vec<Mark>,
+    next_id: u64,
+    fire_on_seek: bool,
+    position: Option<Duration>,
+}
+
+impl Marks {
+    /// Call `callback` with the mark time when playback crosses `time`.
+    pub fn add(&mut self, time: Duration, callback: MarkCallback) -> MarkId {
+        let id = MarkId(self.next_id);
+        self.next_id += 1;
+        let index = self.marks.partition_point(|mark| mark.time <= time);
+        self.marks.insert(index, Mark { id, time, callback });
+        id
+    }
+
+    /// Remove the mark `id`, returns false if there is no such mark.
+    pub fn remove(&mut self, id: MarkId) -> bool {
+        let len = self.marks.len();
+        self.marks.retain(|mark| mark.id != id);
+        self.marks.len() != len
+    }
+
+    /// Fire the marks skipped by forward seeks as well. Off by default.
+    pub fn set_fire_on_seek(&mut self, fire_on_seek: bool) {
+        self.fire_on_seek = fire_on_seek;
+    }
+
+    /// Report the presented `position`, `seeked` is true for the first frame after a seek.
+    pub fn update(&mut self, position: Duration, seeked: bool) {
+        let previous = self.position.replace(position);
+        if seeked && !self.fire_on_seek {
+            return;
+        }
+        let crossed = |time: Duration| match previous {
+            Some(previous) => previous < time && time <= position,
+            None => time <= position,
+        };
+        for mark in self.marks.iter_mut().filter(|mark| crossed(mark.time)) {
+            debug!("crossed mark {:?} at {:?}", mark.time, position);
+            (mark.callback)(mark.time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Marks at 1s and 2s which record their times in the returned list when fired.
+    fn marks() -> (Marks, Arc<Mutex<Vec<Duration>>>) {
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let mut marks = Marks::default();
+        for secs in [2, 1] {
+            let fired = fired.clone();
+            marks.add(
+                Duration::from_secs(secs),
+                Box::new(move |time| fired.lock().unwrap().push(time)),
+            );
+        }
+        (marks, fired)
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn fire_once_on_forward_crossing() {
+        let (mut marks, fired) = marks();
+        for position in [0, 960, 1000, 1040, 1960, 2000, 2040] {
+            marks.update(ms(position), false);
+        }
+        assert_eq!(*fired.lock().unwrap(), vec![ms(1000), ms(2000)]);
+    }
+
+    #[test]
+    fn skip_marks_crossed_by_seek() {
+        let (mut marks, fired) = marks();
+        marks.update(ms(0), false);
+        marks.update(ms(2500), true);
+        marks.update(ms(2540), false);
+        assert!(fired.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn fire_marks_crossed_by_seek_if_enabled() {
+        let (mut marks, fired) = marks();
+        marks.set_fire_on_seek(true);
+        marks.update(ms(0), false);
+        marks.update(ms(2500), true);
+        assert_eq!(*fired.lock().unwrap(), vec![ms(1000), ms(2000)]);
+        // Seeking back only fires the mark again when playback crosses it.
+        marks.update(ms(500), true);
+        assert_eq!(fired.lock().unwrap().len(), 2);
+        marks.update(ms(1000), false);
+        assert_eq!(fired.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn removed_mark_does_not_fire() {
+        let (mut marks, fired) = marks();
+        let id = marks.add(ms(1500), Box::new(|_| panic!("removed mark fired")));
+        assert!(marks.remove(id));
+        assert!(!marks.remove(id));
+        marks.update(ms(0), false);
+        marks.update(ms(1600), false);
+        assert_eq!(*fired.lock().unwrap(), vec![ms(1000)]);
+    }
+}
@@ -0,0 +1,43 @@
+use error_stack::Result;
+use std::time::Duration;
+
+use crate::file_decoder::{FileDecoder, FileDecoderError};
+
+/// Start `file_decoder` and take the frames of the main video stream until the end of the
+/// stream without presenting them, e.g. to test the pipeline without a window. Returns the
+/// number of frames. Nothing drains the audio queue, so build the decoder with
+/// [`crate::FileDecoderBuilder::disable_audio`], or the demuxer stalls once it is full.
+pub fn drain(file_decoder: &mut FileDecoder) -> Result<u64, FileDecoderError> {
+    file_decoder.start()?;
+    let video_queue = file_decoder.video_queue();
+    let mut frames = 0;
+    loop {
+        match video_queue.poll(Duration::from_millis(10)) {
+            Some(video_delay_item) if video_delay_item.data.is_some() => frames += 1,
+            Some(_) => break,
+            None => {}
+        }
+        if let Some(err) = file_decoder.try_take_error() {
+            file_decoder.stop();
+            return Err(err);
+        }
+    }
+    file_decoder.stop();
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tiny_clip, FileDecoderBuilder};
+
+    #[test]
+    fn drain_tiny_clip() {
+        let mut file_decoder = FileDecoderBuilder::new(tiny_clip())
+            .disable_audio(true)
+            .build()
+            .unwrap();
+        assert_eq!((file_decoder.width(), file_decoder.height()), (16, 16));
+        assert_eq!(drain(&mut file_decoder).unwrap(), 10);
+    }
+}
@@ -0,0 +1,155 @@
+use ffmpeg_rs::{
+    format::{self, Pixel},
+    util::frame::video::Video,
+};
+use sdl2::pixels::PixelFormatEnum;
+
+/// SDL pixel format of the textures for frames in `fmt`, `Unknown` if SDL has none. Frames in
+/// such formats have to be converted, see [`displayable_pixel_format`].
+pub fn av_to_sdl_pixel_format_mapper(fmt: &format::Pixel) -> PixelFormatEnum {
+    match fmt {
+        format::Pixel::YUV420P => PixelFormatEnum::IYUV,
+        // RGBA32 is the byte order R, G, B, A like ffmpeg's RGBA, RGBA8888 would be the
+        // packed 32 bit value and swap the bytes on little endian machines.
+        format::Pixel::RGBA => PixelFormatEnum::RGBA32,
+        format::Pixel::RGB24 => PixelFormatEnum::RGB24,
+        format::Pixel::BGR24 => PixelFormatEnum::BGR24,
+        format::Pixel::YUYV422 => PixelFormatEnum::YUY2,
+        format::Pixel::UYVY422 => PixelFormatEnum::UYVY,
+        // e.g. the output of hardware decoders
+        format::Pixel::NV12 => PixelFormatEnum::NV12,
+        // SDL has no luma only textures, the chroma planes are filled in.
+        format::Pixel::GRAY8 => PixelFormatEnum::IYUV,
+        _ => PixelFormatEnum::Unknown,
+    }
+}
+
+/// `pixel_format` if it can be displayed, otherwise a displayable format to convert to
+/// instead. SDL has no textures for e.g. YUV444P or 10 bit formats like P010, RGB keeps its
+/// full chroma resolution.
+pub fn displayable_pixel_format(pixel_format: Pixel) -> Pixel {
+    if av_to_sdl_pixel_format_mapper(&pixel_format) != PixelFormatEnum::Unknown {
+        return pixel_format;
+    }
+    let flags = pixel_format
+        .descriptor()
+        .map_or(0, |descriptor| unsafe { (*descriptor.as_ptr()).flags });
+    if flags & ffmpeg_rs::ffi::AV_PIX_FMT_FLAG_ALPHA as u64 != 0 {
+        Pixel::RGBA
+    } else if flags & ffmpeg_rs::ffi::AV_PIX_FMT_FLAG_RGB as u64 != 0
+        || pixel_format
+            .descriptor()
+            .map_or(false, |descriptor| descriptor.log2_chroma_w() == 0)
+    {
+        Pixel::RGB24
+    } else {
+        Pixel::YUV420P
+    }
+}
+
+/// Pixel formats which can be displayed, i.e. which `av_to_sdl_pixel_format_mapper()` maps to
+/// a SDL pixel format. Use it to validate a requested pixel format.
+pub fn supported_pixel_formats() -> Vec<Pixel> {
+    let mut pixel_formats = Vec::new();
+    let mut descriptor = std::ptr::null();
+    loop {
+        descriptor = unsafe { ffmpeg_rs::ffi::av_pix_fmt_desc_next(descriptor) };
+        if descriptor.is_null() {
+            break;
+        }
+        let pixel_format =
+            Pixel::from(unsafe { ffmpeg_rs::ffi::av_pix_fmt_desc_get_id(descriptor) });
+        if av_to_sdl_pixel_format_mapper(&pixel_format) != PixelFormatEnum::Unknown {
+            pixel_formats.push(pixel_format);
+        }
+    }
+    pixel_formats
+}
+
+/// Copy the NV12 `frame` into the `buffer` of a locked NV12 texture with `pitch`. The texture
+/// expects the interleaved chroma plane (U and V alternating) right behind the luma plane,
+/// both with the pitch of the texture. The frame rows can be padded to a bigger stride.
+pub fn copy_nv12(frame: &Video, buffer: &mut [u8], pitch: usize) {
+    let height = frame.height() as usize;
+    for (plane, offset, rows) in [(0, 0, height), (1, pitch * height, (height + 1) / 2)] {
+        let stride = frame.stride(plane);
+        // A chroma row holds a U and V pair for every two pixels.
+        let row_len = ((frame.width() as usize + 1) & !1).min(stride).min(pitch);
+        let data = frame.data(plane);
+        for row in 0..rows {
+            let start = offset + row * pitch;
+            buffer[start..start + row_len]
+                .copy_from_slice(&data[row * stride..row * stride + row_len]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_format_mapping() {
+        let mappings = [
+            (Pixel::YUV420P, PixelFormatEnum::IYUV),
+            (Pixel::RGBA, PixelFormatEnum::RGBA32),
+            (Pixel::RGB24, PixelFormatEnum::RGB24),
+            (Pixel::BGR24, PixelFormatEnum::BGR24),
+            (Pixel::YUYV422, PixelFormatEnum::YUY2),
+            (Pixel::UYVY422, PixelFormatEnum::UYVY),
+            (Pixel::NV12, PixelFormatEnum::NV12),
+            (Pixel::GRAY8, PixelFormatEnum::IYUV),
+            (Pixel::YUV444P, PixelFormatEnum::Unknown),
+            (Pixel::RGB48LE, PixelFormatEnum::Unknown),
+            (Pixel::P010LE, PixelFormatEnum::Unknown),
+        ];
+        for (pixel_format, sdl_pixel_format) in mappings {
+            assert_eq!(
+                av_to_sdl_pixel_format_mapper(&pixel_format),
+                sdl_pixel_format,
+                "{:?}",
+                pixel_format
+            );
+        }
+    }
+
+    #[test]
+    fn convert_to_displayable_pixel_format() {
+        assert_eq!(displayable_pixel_format(Pixel::NV12), Pixel::NV12);
+        assert_eq!(displayable_pixel_format(Pixel::GRAY8), Pixel::GRAY8);
+        assert_eq!(displayable_pixel_format(Pixel::YUV444P), Pixel::RGB24);
+        assert_eq!(displayable_pixel_format(Pixel::YUV422P), Pixel::YUV420P);
+        assert_eq!(displayable_pixel_format(Pixel::YUVA420P), Pixel::RGBA);
+        assert_eq!(displayable_pixel_format(Pixel::GBRP), Pixel::RGB24);
+        assert_eq!(displayable_pixel_format(Pixel::P010LE), Pixel::YUV420P);
+        assert_eq!(displayable_pixel_format(Pixel::YUV420P10LE), Pixel::YUV420P);
+    }
+
+    #[test]
+    fn copy_nv12_frame() {
+        let (width, height, pitch) = (6, 5, 8);
+        let mut frame = Video::new(Pixel::NV12, width, height);
+        let (y_stride, uv_stride) = (frame.stride(0), frame.stride(1));
+        for row in 0..height as usize {
+            frame.data_mut(0)[row * y_stride..row * y_stride + width as usize].fill(row as u8);
+        }
+        for row in 0..(height as usize + 1) / 2 {
+            for column in 0..width as usize {
+                frame.data_mut(1)[row * uv_stride + column] = 100 + column as u8;
+            }
+        }
+
+        let mut buffer = vec![0; pitch * (height as usize + (height as usize + 1) / 2)];
+        copy_nv12(&frame, &mut buffer, pitch);
+        for row in 0..height as usize {
+            assert_eq!(&buffer[row * pitch..row * pitch + 6], &[row as u8; 6]);
+        }
+        let chroma = pitch * height as usize;
+        for row in 0..3 {
+            assert_eq!(
+                &buffer[chroma + row * pitch..chroma + row * pitch + 6],
+                &[100, 101, 102, 103, 104, 105]
+            );
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use log::{debug, warn};
+use std::{fs, path::Path};
+
+use crate::file_decoder::FileDecoder;
+
+/// Inputs played one after the other by one [`FileDecoder`], see [`Playlist::open_next`].
+#[derive(new, Clone, Debug)]
+pub struct Playlist {
+    uris: Vec<String>,
+    #[new(default)]
+    current: usize,
+}
+
+impl Playlist {
+    /// Playlist of `inputs`, directories are replaced by the files in them sorted by name.
+    /// Hidden files are left out, directories which cannot be read are skipped.
+    pub fn from_inputs(inputs: &[String]) -> Playlist {
+        let mut uris = Vec::new();
+        for input in inputs {
+            if !Path::new(input).is_dir() {
+                uris.push(input.clone());
+                continue;
+            }
+            let entries = match fs::read_dir(input) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    warn!("cannot read directory {}: {}", input, err);
+                    continue;
+                }
+            };
+            let mut files: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.is_file()
+                        && !path
+                            .file_name()
+                            .map_or(true, |name| name.to_string_lossy().starts_with('.'))
+                })
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+            files.sort();
+            debug!("{} files in {}", files.len(), input);
+            uris.extend(files);
+        }
+        Playlist::new(uris)
+    }
+
+    /// The input being played, `None` if the playlist is empty.
+    pub fn current(&self) -> Option<&str> {
+        self.uris.get(self.current).map(String::as_str)
+    }
+
+    /// True if an input follows the current one.
+    pub fn has_next(&self) -> bool {
+        self.current + 1 < self.uris.len()
+    }
+
+    /// Switch `file_decoder` to the next input with [`FileDecoder::open`] and return the
+    /// serial of its frames. Inputs which cannot be opened are skipped with a warning.
+    /// Returns `None` at the end of the playlist, the decoder is stopped then if one of the
+    /// remaining inputs failed.
+    pub fn open_next(&mut self, file_decoder: &mut FileDecoder) -> Option<u64> {
+        while self.has_next() {
+            self.current += 1;
+            let uri = &self.uris[self.current];
+            match file_decoder.open(uri.clone()) {
+                Ok(serial) => return Some(serial),
+                Err(err) => warn!("cannot open {}, skip it: {:?}", uri, err),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testdata, tiny_clip, FileDecoderBuilder};
+    use std::time::Duration;
+
+    #[test]
+    fn from_inputs_expands_directories() {
+        let playlist =
+            Playlist::from_inputs(&[testdata(""), "https://example.com/stream.m3u8".to_owned()]);
+        assert_eq!(
+            playlist.uris,
+            vec![
+                testdata("README.md"),
+                testdata("av.avi"),
+                testdata("ibp.m1v"),
+                testdata("resize.m1v"),
+                tiny_clip(),
+                "https://example.com/stream.m3u8".to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    fn open_next_skips_broken_inputs() {
+        let mut playlist = Playlist::new(vec![
+            tiny_clip(),
+            "does-not-exist.mp4".to_owned(),
+            tiny_clip(),
+        ]);
+        let mut file_decoder = FileDecoderBuilder::new(tiny_clip())
+            .disable_audio(true)
+            .build()
+            .unwrap();
+        file_decoder.start().unwrap();
+
+        let serial = playlist.open_next(&mut file_decoder).unwrap();
+        assert_eq!(playlist.current, 2);
+        assert!(!playlist.has_next());
+        let video_data = file_decoder
+            .video_queue()
+            .poll(Duration::from_secs(5))
+            .expect("no frame")
+            .data
+            .expect("end of stream");
+        assert_eq!(video_data.serial, serial);
+        assert_eq!(playlist.open_next(&mut file_decoder), None);
+        file_decoder.stop();
+    }
+}
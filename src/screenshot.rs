@@ -0,0 +1,71 @@
+use error_stack::{Context, IntoReport, Result, ResultExt};
+use ffmpeg_rs::{
+    format::Pixel,
+    software::scaling::{context, flag::Flags},
+    util::frame::video::Video,
+};
+use std::{fmt, fs::File, io::BufWriter, path::Path};
+
+#[derive(Debug)]
+pub struct ScreenshotError;
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Screenshot error")
+    }
+}
+
+impl Context for ScreenshotError {}
+
+/// Write `frame` as RGB PNG to `path`. Frames in other pixel formats are converted with their
+/// own scaler, the frame itself is not touched.
+pub fn save_png(frame: &Video, path: &Path) -> Result<(), ScreenshotError> {
+    let mut rgb_frame = Video::empty();
+    let frame = if frame.format() == Pixel::RGB24 {
+        frame
+    } else {
+        let mut scaler = context::Context::get(
+            frame.format(),
+            frame.width(),
+            frame.height(),
+            Pixel::RGB24,
+            frame.width(),
+            frame.height(),
+            Flags::BILINEAR,
+        )
+        .into_report()
+        .attach_printable("Cannot get scaling context")
+        .change_context(ScreenshotError)?;
+        scaler
+            .run(frame, &mut rgb_frame)
+            .into_report()
+            .attach_printable("Scaling failed")
+            .change_context(ScreenshotError)?;
+        &rgb_frame
+    };
+
+    // The rows of the frame are padded to the stride, the PNG rows are not.
+    let row_len = frame.width() as usize * 3;
+    let stride = frame.stride(0);
+    let data: Vec<u8> = frame
+        .data(0)
+        .chunks(stride)
+        .take(frame.height() as usize)
+        .flat_map(|row| &row[..row_len])
+        .copied()
+        .collect();
+
+    let file = File::create(path)
+        .into_report()
+        .attach_printable(format!("Cannot create {}", path.display()))
+        .change_context(ScreenshotError)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), frame.width(), frame.height());
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .into_report()
+        .attach_printable(format!("Cannot write {}", path.display()))
+        .change_context(ScreenshotError)
+}
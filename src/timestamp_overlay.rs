@@ -0,0 +1,127 @@
+use ffmpeg_rs::{format::Pixel, util::frame::video::Video};
+
+// 3x5 pixel glyphs, one row per byte with the leftmost pixel in bit 2.
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const DIGITS: [[u8; GLYPH_HEIGHT]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+const COLON: [u8; GLYPH_HEIGHT] = [0b000, 0b010, 0b000, 0b010, 0b000];
+const DOT: [u8; GLYPH_HEIGHT] = [0b000, 0b000, 0b000, 0b000, 0b010];
+const MINUS: [u8; GLYPH_HEIGHT] = [0b000, 0b000, 0b111, 0b000, 0b000];
+const SPACE: [u8; GLYPH_HEIGHT] = [0; GLYPH_HEIGHT];
+
+// Every glyph pixel becomes a SCALE x SCALE block.
+const SCALE: usize = 4;
+const MARGIN: usize = 2 * SCALE;
+
+// Limited range luma of the text and the box behind it, chroma is set to neutral.
+const TEXT_LUMA: u8 = 235;
+const BOX_LUMA: u8 = 16;
+const NEUTRAL_CHROMA: u8 = 128;
+
+/// True if [`burn_in`] can draw into frames of `pixel_format`.
+pub fn is_supported(pixel_format: Pixel) -> bool {
+    matches!(pixel_format, Pixel::YUYV422 | Pixel::UYVY422)
+        || planar_yuv_chroma_shift(pixel_format).is_some()
+}
+
+/// Draw `text` (digits, ':', '.', '-' and spaces) on a black box into the top left corner
+/// of the pixels of `frame`. Other characters are drawn as spaces. Does nothing if the pixel
+/// format is not supported, see [`is_supported`].
+pub fn burn_in(frame: &mut Video, text: &str) {
+    let glyphs: Vec<[u8; GLYPH_HEIGHT]> = text
+        .chars()
+        .map(|c| match c {
+            '0'..='9' => DIGITS[c as usize - '0' as usize],
+            ':' => COLON,
+            '.' => DOT,
+            '-' => MINUS,
+            _ => SPACE,
+        })
+        .collect();
+    let box_width =
+        (2 * MARGIN + glyphs.len() * (GLYPH_WIDTH + 1) * SCALE).min(frame.width() as usize);
+    let box_height = (2 * MARGIN + GLYPH_HEIGHT * SCALE).min(frame.height() as usize);
+    let is_text = |x: usize, y: usize| {
+        if x < MARGIN || y < MARGIN {
+            return false;
+        }
+        let (x, y) = ((x - MARGIN) / SCALE, (y - MARGIN) / SCALE);
+        let (glyph, column) = (x / (GLYPH_WIDTH + 1), x % (GLYPH_WIDTH + 1));
+        y < GLYPH_HEIGHT
+            && column < GLYPH_WIDTH
+            && glyphs
+                .get(glyph)
+                .map_or(false, |rows| rows[y] & (0b100 >> column) != 0)
+    };
+    let luma = |x: usize, y: usize| if is_text(x, y) { TEXT_LUMA } else { BOX_LUMA };
+
+    match frame.format() {
+        Pixel::YUYV422 | Pixel::UYVY422 => {
+            let luma_offset = usize::from(frame.format() == Pixel::UYVY422);
+            let stride = frame.stride(0);
+            let data = frame.data_mut(0);
+            for y in 0..box_height {
+                for x in 0..box_width {
+                    let offset = y * stride + x * 2;
+                    data[offset + luma_offset] = luma(x, y);
+                    data[offset + 1 - luma_offset] = NEUTRAL_CHROMA;
+                }
+            }
+        }
+        pixel_format => {
+            let (shift_x, shift_y) = match planar_yuv_chroma_shift(pixel_format) {
+                Some(shift) => shift,
+                None => return,
+            };
+            let stride = frame.stride(0);
+            let data = frame.data_mut(0);
+            for y in 0..box_height {
+                for x in 0..box_width {
+                    data[y * stride + x] = luma(x, y);
+                }
+            }
+            for plane in 1..frame.planes().min(3) {
+                let stride = frame.stride(plane);
+                let data = frame.data_mut(plane);
+                for y in 0..(box_height >> shift_y) {
+                    for x in 0..(box_width >> shift_x) {
+                        data[y * stride + x] = NEUTRAL_CHROMA;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Chroma subsampling of 8 bit planar YUV and gray formats, semi-planar formats like NV12 are
+// not supported.
+fn planar_yuv_chroma_shift(pixel_format: Pixel) -> Option<(u8, u8)> {
+    let descriptor = pixel_format.descriptor()?;
+    let (flags, components) =
+        unsafe { ((*descriptor.as_ptr()).flags, (*descriptor.as_ptr()).comp) };
+    let is_planar = flags & ffmpeg_rs::ffi::AV_PIX_FMT_FLAG_PLANAR as u64 != 0;
+    let is_rgb = flags & ffmpeg_rs::ffi::AV_PIX_FMT_FLAG_RGB as u64 != 0;
+    let is_gray = descriptor.nb_components() == 1;
+    let is_semi_planar =
+        descriptor.nb_components() >= 3 && components[1].plane == components[2].plane;
+    if components[0].depth != 8
+        || is_rgb
+        || is_semi_planar
+        || !(is_planar || is_gray)
+        || descriptor.nb_components() > 3
+    {
+        return None;
+    }
+    Some((descriptor.log2_chroma_w(), descriptor.log2_chroma_h()))
+}
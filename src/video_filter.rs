@@ -0,0 +1,140 @@
+use ffmpeg_rs::{filter, format::Pixel, util::frame::video::Video, Error, Rational, Rescale};
+use std::ffi::CString;
+
+/// FFmpeg filters (e.g. "eq" or "yadif") between a buffer source and a buffer sink, for
+/// frames of one format and size.
+pub struct VideoFilter {
+    graph: filter::Graph,
+    format: Pixel,
+    width: u32,
+    height: u32,
+    // Time base of the input frames, the output frames are rescaled to it.
+    time_base: Rational,
+}
+
+impl VideoFilter {
+    /// Build the graph of `filters` (FFmpeg filtergraph syntax) for frames of `format` in
+    /// `width` x `height` with timestamps in `time_base`.
+    pub fn new(
+        filters: &str,
+        format: Pixel,
+        width: u32,
+        height: u32,
+        time_base: Rational,
+        sample_aspect_ratio: Rational,
+    ) -> Result<VideoFilter, Error> {
+        let mut graph = filter::Graph::new();
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect={}/{}",
+            width,
+            height,
+            ffmpeg_rs::ffi::AVPixelFormat::from(format) as i32,
+            time_base.numerator(),
+            time_base.denominator(),
+            sample_aspect_ratio.numerator().max(1),
+            sample_aspect_ratio.denominator().max(1)
+        );
+        graph.add(
+            &filter::find("buffer").ok_or(Error::FilterNotFound)?,
+            "in",
+            &args,
+        )?;
+        graph.add(
+            &filter::find("buffersink").ok_or(Error::FilterNotFound)?,
+            "out",
+            "",
+        )?;
+        graph.output("in", 0)?.input("out", 0)?.parse(filters)?;
+        graph.validate()?;
+        Ok(VideoFilter {
+            graph,
+            format,
+            width,
+            height,
+            time_base,
+        })
+    }
+
+    /// True if `frame` has the format and size the graph was built for.
+    pub fn accepts(&self, frame: &Video) -> bool {
+        frame.format() == self.format
+            && frame.width() == self.width
+            && frame.height() == self.height
+    }
+
+    /// Send `command` with `arg` to the filters named `target`, e.g. to change a parameter
+    /// without rebuilding the graph.
+    pub fn send_command(&mut self, target: &str, command: &str, arg: &str) -> Result<(), Error> {
+        let to_c_string = |value: &str| CString::new(value).map_err(|_| Error::InvalidData);
+        let (target, command, arg) = (
+            to_c_string(target)?,
+            to_c_string(command)?,
+            to_c_string(arg)?,
+        );
+        let ret = unsafe {
+            ffmpeg_rs::ffi::avfilter_graph_send_command(
+                self.graph.as_mut_ptr(),
+                target.as_ptr(),
+                command.as_ptr(),
+                arg.as_ptr(),
+                std::ptr::null_mut(),
+                0,
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::from(ret));
+        }
+        Ok(())
+    }
+
+    /// Filter `frame`. Returns the frames the graph puts out, which can be none while a
+    /// filter waits for more input or several, e.g. with a doubled frame rate.
+    pub fn run(&mut self, frame: &Video) -> Result<Vec<Video>, Error> {
+        self.graph
+            .get("in")
+            .ok_or(Error::Bug)?
+            .source()
+            .add(frame)?;
+        self.receive()
+    }
+
+    /// Signal the end of the input. Returns the frames the filters still held back.
+    pub fn flush(&mut self) -> Result<Vec<Video>, Error> {
+        self.graph.get("in").ok_or(Error::Bug)?.source().flush()?;
+        self.receive()
+    }
+
+    fn receive(&mut self) -> Result<Vec<Video>, Error> {
+        let mut sink = self.graph.get("out").ok_or(Error::Bug)?;
+        // Filters may change the time base, e.g. yadif halves it when it doubles the frame
+        // rate. Frames created between two input frames can then only be as exact as the
+        // input time base.
+        let output_time_base =
+            Rational::from(unsafe { ffmpeg_rs::ffi::av_buffersink_get_time_base(sink.as_ptr()) });
+        let mut frames = Vec::new();
+        loop {
+            let mut filtered = Video::empty();
+            match sink.sink().frame(&mut filtered) {
+                Ok(()) => {
+                    // Filters set the pts of the frames they create, the consumers read the
+                    // best effort timestamp in the input time base.
+                    let pts = filtered
+                        .pts()
+                        .map(|pts| pts.rescale(output_time_base, self.time_base));
+                    filtered.set_pts(pts);
+                    unsafe {
+                        (*filtered.as_mut_ptr()).best_effort_timestamp =
+                            pts.unwrap_or(ffmpeg_rs::ffi::AV_NOPTS_VALUE);
+                    }
+                    frames.push(filtered);
+                }
+                Err(Error::Eof)
+                | Err(Error::Other {
+                    errno: ffmpeg_rs::util::error::EAGAIN,
+                }) => return Ok(frames),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}